@@ -1,26 +1,158 @@
 use macroquad::prelude::*;
 use std::f32::consts::PI;
 
+use crate::decoration::Textures;
 use crate::fx;
 use crate::level::*;
+use crate::skin::Skin;
+
+// length of one physics step in seconds
+pub const PHYSICS_DT: f32 = 0.0002;
+
+pub const HEAD_R: f32 = 4.4;
+const HEAD_Y: f32 = -21.0;
+// radius around the frame center for picking up stars
+const FRAME_R: f32 = 13.0;
+
+// Tunable physics of a bike. Profiles in assets/bikes.json and level properties
+// named "bike.<field>" override single fields of the defaults below.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct BikeParams {
+    pub gravity: f32,
+    pub frame_mass: f32,
+    pub frame_inertia: f32,
+    pub wheel_mass: f32,
+    pub wheel_inertia: f32,
+    pub wheel_r: f32,
+    pub wheel_x: f32,
+    pub wheel_y: f32,
+    pub suspension: f32,
+    pub suspension_friction: f32,
+    pub break_friction: f32,
+    pub max_speed: f32,
+    pub gas: f32,
+    pub jump_strength: f32,
+    pub jump_duration: f32,
+    pub jump_pause: f32,
+}
+
+impl Default for BikeParams {
+    fn default() -> Self {
+        BikeParams {
+            gravity: 100.0,
+            frame_mass: 20.0,
+            frame_inertia: 5000.0,
+            wheel_mass: 1.0,
+            wheel_inertia: 50.0,
+            wheel_r: 8.0,
+            wheel_x: 17.0,
+            wheel_y: 12.0,
+            suspension: 1200.0,
+            suspension_friction: 70.0,
+            break_friction: 7000.0,
+            max_speed: 50.0,
+            gas: 18000.0,
+            jump_strength: 8.0,
+            jump_duration: 0.1,
+            jump_pause: 0.5,
+        }
+    }
+}
+
+impl BikeParams {
+    fn fields(&mut self) -> [(&'static str, &mut f32); 16] {
+        [
+            ("gravity", &mut self.gravity),
+            ("frame_mass", &mut self.frame_mass),
+            ("frame_inertia", &mut self.frame_inertia),
+            ("wheel_mass", &mut self.wheel_mass),
+            ("wheel_inertia", &mut self.wheel_inertia),
+            ("wheel_r", &mut self.wheel_r),
+            ("wheel_x", &mut self.wheel_x),
+            ("wheel_y", &mut self.wheel_y),
+            ("suspension", &mut self.suspension),
+            ("suspension_friction", &mut self.suspension_friction),
+            ("break_friction", &mut self.break_friction),
+            ("max_speed", &mut self.max_speed),
+            ("gas", &mut self.gas),
+            ("jump_strength", &mut self.jump_strength),
+            ("jump_duration", &mut self.jump_duration),
+            ("jump_pause", &mut self.jump_pause),
+        ]
+    }
+
+    // returns false for unknown names
+    pub fn set(&mut self, name: &str, value: f32) -> bool {
+        match self.fields().into_iter().find(|(n, _)| *n == name) {
+            Some((_, v)) => {
+                *v = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // identical params always give the same hash
+    pub fn hash(&self) -> u32 {
+        let mut copy = *self;
+        let bytes = copy.fields().map(|(_, v)| v.to_bits().to_le_bytes());
+        crate::fnv1a(bytes.iter().flatten())
+    }
+}
+
+pub struct Profile {
+    pub name: String,
+    pub params: BikeParams,
+}
+
+// Profiles are a JSON array of objects with a "name" and any BikeParams fields
+// that differ from the defaults.
+pub fn parse_profiles(string: &str) -> Result<Vec<Profile>, std::io::Error> {
+    let invalid = |msg: String| std::io::Error::new(std::io::ErrorKind::InvalidData, msg);
+    let json: serde_json::Value = serde_json::from_str(string)?;
+    let mut profiles = vec![];
+    for p in json
+        .as_array()
+        .ok_or_else(|| invalid("expected an array of profiles".to_string()))?
+    {
+        let o = p
+            .as_object()
+            .ok_or_else(|| invalid("expected a profile object".to_string()))?;
+        let name = o
+            .get("name")
+            .and_then(|n| n.as_str())
+            .ok_or_else(|| invalid("missing profile name".to_string()))?;
+        let mut params = BikeParams::default();
+        for (k, v) in o.iter().filter(|(k, _)| *k != "name") {
+            let value = v
+                .as_f64()
+                .ok_or_else(|| invalid(format!("{}: {} is not a number", name, k)))?;
+            if !params.set(k, value as f32) {
+                return Err(invalid(format!("{}: unknown parameter {}", name, k)));
+            }
+        }
+        profiles.push(Profile {
+            name: name.to_string(),
+            params,
+        });
+    }
+    if profiles.is_empty() {
+        return Err(invalid("no profiles".to_string()));
+    }
+    Ok(profiles)
+}
 
-const GRAVITY: f32 = 100.0;
-const FRAME_MASS: f32 = 20.0;
-const FRAME_INERTIA: f32 = 5000.0;
-const WHEEL_MASS: f32 = 1.0;
-const WHEEL_INERTIA: f32 = 50.0;
-const WHEEL_R: f32 = 8.0;
-const WHEEL_X: f32 = 17.0;
-const WHEEL_Y: f32 = 12.0;
-const SUSPENSION: f32 = 1200.0;
-const SUSPENSION_FRICTION: f32 = 70.0;
-const BREAK_FRICTION: f32 = 7000.0;
-const MAX_SPEED: f32 = 50.0;
-const GAS: f32 = 18000.0;
-
-const JUMP_STRENGTH: f32 = 8.0;
-const JUMP_DURATION: f32 = 0.1;
-const JUMP_PAUSE: f32 = 0.5;
+// for tools running without a window
+pub fn load_profiles_file(path: &str) -> Result<Vec<Profile>, std::io::Error> {
+    parse_profiles(&std::fs::read_to_string(path)?)
+}
+
+pub async fn load_profiles(path: &str) -> Result<Vec<Profile>, std::io::Error> {
+    let string = macroquad::file::load_string(path)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::NotFound, e.to_string()))?;
+    parse_profiles(&string)
+}
 
 #[derive(PartialEq, Clone, Copy, Default)]
 pub enum Direction {
@@ -29,73 +161,79 @@ pub enum Direction {
     Left,
 }
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Copy)]
 pub enum WheelInput {
     None,
     Break,
     Accelerate,
 }
+#[derive(Clone, Copy)]
 pub struct Input {
     pub toggle_dir: bool,
     pub wheel: WheelInput,
     pub jump: Option<Direction>,
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Body {
     pub pos: Vec2,
-    ang: f32,
-    vel: Vec2,
-    ang_vel: f32,
+    pub ang: f32,
+    pub vel: Vec2,
+    pub ang_vel: f32,
     force: Vec2,
     torque: f32,
 }
 
+#[derive(Clone)]
 pub struct Jump {
     dir: Direction,
     time: f32,
     ang_vel: f32,
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Bike {
     pub alive: bool,
     pub frame: Body,
-    wheels: [Body; 2],
-    dir: Direction,
+    pub wheels: [Body; 2],
+    // wall contacts of the last update
+    pub contacts: [Option<CollisionInfo>; 2],
+    pub spring_forces: [Vec2; 2],
+    pub dir: Direction,
+    pub params: BikeParams,
     dir_lerp: f32,
     prev_toggle_dir: bool,
     jump: Option<Jump>,
 }
 
-fn update_frame(frame: &mut Body, dt: f32) {
-    frame.ang_vel += frame.torque / FRAME_INERTIA * dt;
-    frame.vel += frame.force / FRAME_MASS * dt;
+fn update_frame(frame: &mut Body, dt: f32, p: &BikeParams) {
+    frame.ang_vel += frame.torque / p.frame_inertia * dt;
+    frame.vel += frame.force / p.frame_mass * dt;
     frame.ang += frame.ang_vel * dt;
     frame.ang %= 2.0 * PI;
     frame.pos += frame.vel * dt;
     // reset forces
     frame.torque = 0.0;
-    frame.force = vec2(0.0, GRAVITY * FRAME_MASS);
+    frame.force = vec2(0.0, p.gravity * p.frame_mass);
 }
 
-fn update_wheel(wheel: &mut Body, dt: f32, ci: Option<CollisionInfo>) {
+fn update_wheel(wheel: &mut Body, dt: f32, ci: Option<CollisionInfo>, p: &BikeParams) {
     let mut b = false;
     if let Some(ci) = ci {
         wheel.pos += ci.normal * ci.dist;
 
         if ci.normal.dot(wheel.vel) < 0.0 {
-            wheel.ang_vel = ci.normal.perp_dot(wheel.vel) / WHEEL_R;
-            wheel.torque += ci.normal.perp_dot(wheel.force) * WHEEL_R;
+            wheel.ang_vel = ci.normal.perp_dot(wheel.vel) / p.wheel_r;
+            wheel.torque += ci.normal.perp_dot(wheel.force) * p.wheel_r;
 
-            wheel.ang_vel += wheel.torque / WHEEL_INERTIA * dt;
-            wheel.vel = ci.normal.perp() * wheel.ang_vel * WHEEL_R;
+            wheel.ang_vel += wheel.torque / p.wheel_inertia * dt;
+            wheel.vel = ci.normal.perp() * wheel.ang_vel * p.wheel_r;
             b = true;
         }
     }
     if !b {
-        wheel.ang_vel += wheel.torque / WHEEL_INERTIA * dt;
-        wheel.vel += wheel.force / WHEEL_MASS * dt;
+        wheel.ang_vel += wheel.torque / p.wheel_inertia * dt;
+        wheel.vel += wheel.force / p.wheel_mass * dt;
     }
 
     wheel.ang += wheel.ang_vel * dt;
@@ -104,36 +242,49 @@ fn update_wheel(wheel: &mut Body, dt: f32, ci: Option<CollisionInfo>) {
 
     // reset forces
     wheel.torque = 0.0;
-    wheel.force = vec2(0.0, GRAVITY * WHEEL_MASS);
+    wheel.force = vec2(0.0, p.gravity * p.wheel_mass);
 }
 
 impl Bike {
-    pub fn new(pos: Vec2) -> Bike {
+    pub fn new(pos: Vec2, params: BikeParams) -> Bike {
         let pos = pos + vec2(0.0, -20.0);
         Bike {
             alive: true,
             frame: Body {
-                pos: pos,
+                pos,
                 ..Default::default()
             },
             wheels: [
                 Body {
-                    pos: pos + vec2(-WHEEL_X, WHEEL_Y),
+                    pos: pos + vec2(-params.wheel_x, params.wheel_y),
                     ..Default::default()
                 },
                 Body {
-                    pos: pos + vec2(WHEEL_X, WHEEL_Y),
+                    pos: pos + vec2(params.wheel_x, params.wheel_y),
                     ..Default::default()
                 },
             ],
+            contacts: [None, None],
+            spring_forces: [Vec2::ZERO; 2],
             dir: Direction::Right,
+            params,
             dir_lerp: 1.0,
             prev_toggle_dir: false,
             jump: None,
         }
     }
 
+    pub fn head(&self) -> Vec2 {
+        self.frame.pos + vec2(0.0, HEAD_Y).rotate(Vec2::from_angle(self.frame.ang))
+    }
+
+    pub fn wheel_contact(&self) -> [bool; 2] {
+        self.contacts.map(|c| c.is_some())
+    }
+
     pub fn update(&mut self, dt: f32, level: &mut Level, input: &Input) {
+        let p = self.params;
+
         // toggle dir
         if input.toggle_dir && !self.prev_toggle_dir {
             self.dir = match self.dir {
@@ -153,7 +304,7 @@ impl Bike {
         if breaking {
             for wheel in self.wheels.iter_mut() {
                 let dv = wheel.ang_vel - self.frame.ang_vel;
-                let torque = dv * BREAK_FRICTION;
+                let torque = dv * p.break_friction;
                 wheel.torque -= torque;
                 self.frame.torque += torque;
             }
@@ -164,8 +315,8 @@ impl Bike {
             Direction::Right => (&mut self.wheels[0], 1.0),
             Direction::Left => (&mut self.wheels[1], -1.0),
         };
-        if input.wheel == WheelInput::Accelerate && wheel.ang_vel * sign < MAX_SPEED {
-            let torque = GAS * sign;
+        if input.wheel == WheelInput::Accelerate && wheel.ang_vel * sign < p.max_speed {
+            let torque = p.gas * sign;
             wheel.torque += torque;
             self.frame.torque -= torque;
             // XXX: is this correct?
@@ -177,11 +328,12 @@ impl Bike {
         let rot = Vec2::from_angle(self.frame.ang);
         for (i, wheel) in self.wheels.iter_mut().enumerate() {
             let arm = match i {
-                0 => vec2(-WHEEL_X, WHEEL_Y),
-                _ => vec2(WHEEL_X, WHEEL_Y),
+                0 => vec2(-p.wheel_x, p.wheel_y),
+                _ => vec2(p.wheel_x, p.wheel_y),
             };
             let arm = arm.rotate(rot);
-            let force = (self.frame.pos + arm - wheel.pos) * SUSPENSION;
+            let force = (self.frame.pos + arm - wheel.pos) * p.suspension;
+            self.spring_forces[i] = force;
 
             wheel.force += force;
             self.frame.force -= force;
@@ -192,7 +344,7 @@ impl Bike {
             let arm = wheel.pos - self.frame.pos;
 
             let dv = self.frame.vel + self.frame.ang_vel * arm.perp() - wheel.vel;
-            let force = dv * SUSPENSION_FRICTION;
+            let force = dv * p.suspension_friction;
 
             wheel.force += force;
             self.frame.force -= force;
@@ -202,17 +354,17 @@ impl Bike {
         // start jump
         if let (Some(dir), None) = (input.jump, &self.jump) {
             self.jump = Some(Jump {
-                dir: dir,
+                dir,
                 time: 0.0,
                 ang_vel: match dir {
                     Direction::Left => {
                         let res = self.frame.ang_vel.min(0.0);
-                        self.frame.ang_vel -= JUMP_STRENGTH;
+                        self.frame.ang_vel -= p.jump_strength;
                         res
                     }
                     Direction::Right => {
                         let res = self.frame.ang_vel.max(0.0);
-                        self.frame.ang_vel += JUMP_STRENGTH;
+                        self.frame.ang_vel += p.jump_strength;
                         res
                     }
                 },
@@ -222,26 +374,26 @@ impl Bike {
         if let Some(jump) = &mut self.jump {
             let t = jump.time;
             jump.time += dt;
-            if t <= JUMP_DURATION && jump.time > JUMP_DURATION {
+            if t <= p.jump_duration && jump.time > p.jump_duration {
                 match jump.dir {
                     Direction::Left => {
-                        self.frame.ang_vel += JUMP_STRENGTH;
+                        self.frame.ang_vel += p.jump_strength;
                         self.frame.ang_vel = self.frame.ang_vel.min(jump.ang_vel);
                     }
                     Direction::Right => {
-                        self.frame.ang_vel -= JUMP_STRENGTH;
+                        self.frame.ang_vel -= p.jump_strength;
                         self.frame.ang_vel = self.frame.ang_vel.max(jump.ang_vel);
                     }
                 };
             }
-            if jump.time > JUMP_DURATION + JUMP_PAUSE {
+            if jump.time > p.jump_duration + p.jump_pause {
                 self.jump = None
             }
         }
 
-        update_frame(&mut self.frame, dt);
-        for wheel in self.wheels.iter_mut() {
-            let ci = match level.circle_collision(wheel.pos, WHEEL_R) {
+        update_frame(&mut self.frame, dt, &p);
+        for (wheel, contact) in self.wheels.iter_mut().zip(self.contacts.iter_mut()) {
+            let ci = match level.circle_collision(wheel.pos, p.wheel_r) {
                 CollisionResult::Lava => {
                     self.alive = false;
                     None
@@ -249,24 +401,25 @@ impl Bike {
                 CollisionResult::None => None,
                 CollisionResult::Wall(ci) => Some(ci),
             };
-            update_wheel(wheel, dt, ci);
+            *contact = ci;
+            update_wheel(wheel, dt, *contact, &p);
         }
 
         // head collision
-        let rot = Vec2::from_angle(self.frame.ang);
-        let head = self.frame.pos + vec2(0.0, -21.0).rotate(rot);
-        if level.circle_collision(head, 4.4) != CollisionResult::None {
+        let head = self.head();
+        if level.circle_collision(head, HEAD_R) != CollisionResult::None {
             self.alive = false;
         }
 
         // pick up stars
-        level.pickup_stars(head, 4.4);
-        level.pickup_stars(self.frame.pos, 13.0);
-        level.pickup_stars(self.wheels[0].pos, WHEEL_R);
-        level.pickup_stars(self.wheels[1].pos, WHEEL_R);
+        level.pickup_stars(head, HEAD_R);
+        level.pickup_stars(self.frame.pos, FRAME_R);
+        level.pickup_stars(self.wheels[0].pos, p.wheel_r);
+        level.pickup_stars(self.wheels[1].pos, p.wheel_r);
     }
 
-    pub fn draw(&self) {
+    // shapes go into the batch, which is flushed before each texture
+    pub fn draw(&self, skin: &Skin, textures: &Textures, batch: &mut fx::Batch) {
         let trans = Affine2::from_scale_angle_translation(
             vec2(self.dir_lerp, 1.0), //
             self.frame.ang,
@@ -275,54 +428,73 @@ impl Bike {
         let t = |x: i32, y: i32| trans.transform_point2(vec2(x as f32, y as f32));
 
         // wheels
-        let c = Color::from_rgba(130, 130, 130, 255);
+        let r = self.params.wheel_r;
+        let wheel_texture = skin.wheel_texture.as_ref().and_then(|p| textures.get(p));
         for w in self.wheels.iter() {
-            fx::draw_wheel(w.pos, w.ang, WHEEL_R, c);
+            match wheel_texture {
+                Some(texture) => {
+                    batch.flush();
+                    let params = DrawTextureParams {
+                        dest_size: Some(Vec2::splat(r * 2.0)),
+                        rotation: w.ang,
+                        ..Default::default()
+                    };
+                    draw_texture_ex(texture, w.pos.x - r, w.pos.y - r, WHITE, params);
+                }
+                None => batch.wheel(w.pos, w.ang, r, skin.wheel_color),
+            }
         }
 
         // springs
-        let c = Color::from_rgba(140, 80, 70, 255);
+        let c = skin.spring_color;
+        let (sw, sv) = skin.limb("spring");
         let lerp = 0.5 + (self.dir_lerp * 0.5 * PI).sin() * 0.5;
         let w0 = self.wheels[1].pos.lerp(self.wheels[0].pos, lerp);
         let w1 = self.wheels[0].pos.lerp(self.wheels[1].pos, lerp);
-        fx::draw_limb(t(0, 9), w0, 3.0, 3.0, c);
-        fx::draw_limb(t(12, -1), w1, 3.0, 3.0, c);
+        batch.limb(t(0, 9), w0, sw, sv, c);
+        batch.limb(t(12, -1), w1, sw, sv, c);
 
         // frame
-        fx::draw_polygon(
-            &[
-                t(2, -3),
-                t(9, -9),
-                t(14, -4),
-                t(1, 11),
-                t(-1, 11),
-                t(-11, 0),
-                t(-17, -2),
-                t(-17, -7),
-                t(-10, -7),
-            ],
-            Color::from_rgba(70, 60, 50, 255),
-        );
+        match skin.frame_texture.as_ref().and_then(|p| textures.get(p)) {
+            Some(texture) => {
+                batch.flush();
+                // mirrored along with the bike when it turns around
+                let rect = skin.frame_texture_rect;
+                let left = (rect.x * self.dir_lerp).min(rect.right() * self.dir_lerp);
+                let params = DrawTextureParams {
+                    dest_size: Some(vec2(rect.w * self.dir_lerp.abs(), rect.h)),
+                    rotation: self.frame.ang,
+                    flip_x: self.dir_lerp < 0.0,
+                    pivot: Some(self.frame.pos),
+                    ..Default::default()
+                };
+                let p = self.frame.pos + vec2(left, rect.y);
+                draw_texture_ex(texture, p.x, p.y, WHITE, params);
+            }
+            None => {
+                let points: Vec<Vec2> = skin
+                    .frame
+                    .iter()
+                    .map(|p| trans.transform_point2(*p))
+                    .collect();
+                batch.polygon(&points, skin.frame_color);
+            }
+        }
 
         // rider
-        let c = Color::from_rgba(130, 130, 130, 255);
-        let limb = |x1: i32, y1: i32, x2: i32, y2: i32, w: f32, v: f32, c: Color| {
-            fx::draw_limb(t(x1, y1), t(x2, y2), w, v, c);
-        };
+        let c = skin.rider_color;
         // head
-        let q = t(0, -21);
-        draw_poly(q.x, q.y, 16, 4.5, self.frame.ang * (180.0 / PI), c);
-        // body
-        limb(-3, -15, -10, -9, 6.5, 6.8, c);
-        // leg
-        limb(-10, -9, -2, -3, 7.0, 4.0, c);
-        limb(-2, -3, -1, 6, 4.0, 3.0, c);
-        limb(-1, 6, 2, 6, 3.0, 2.0, c);
+        batch.circle(t(0, -21), skin.head_r, 16, self.frame.ang, c);
+        let mut limb = |x1: i32, y1: i32, x2: i32, y2: i32, name: &str| {
+            let (w, v) = skin.limb(name);
+            batch.limb(t(x1, y1), t(x2, y2), w, v, c);
+        };
+        limb(-3, -15, -10, -9, "body");
+        limb(-10, -9, -2, -3, "thigh");
+        limb(-2, -3, -1, 6, "shin");
+        limb(-1, 6, 2, 6, "foot");
 
         // arm
-        // TODO: clean up this mess
-        // limb(-1, -15, 2, -8, 4.0, 3.0, c);
-        // limb(2, -8, 10, -7, 3.0, 2.5, c);
         let ang = match &self.jump {
             Some(jump) => {
                 let t = jump.time;
@@ -338,11 +510,12 @@ impl Bike {
         let trans =
             trans * Affine2::from_scale_angle_translation(Vec2::ONE, ang, vec2(-1.0, -15.0));
         let t = |x: i32, y: i32| trans.transform_point2(vec2(x as f32, y as f32));
-        let limb = |x1: i32, y1: i32, x2: i32, y2: i32, w: f32, v: f32, c: Color| {
-            fx::draw_limb(t(x1, y1), t(x2, y2), w, v, c);
+        let mut limb = |x1: i32, y1: i32, x2: i32, y2: i32, name: &str| {
+            let (w, v) = skin.limb(name);
+            batch.limb(t(x1, y1), t(x2, y2), w, v, c);
         };
 
-        limb(0, 0, 3, 7, 4.0, 3.0, c);
-        limb(3, 7, 11, 8, 3.0, 2.5, c);
+        limb(0, 0, 3, 7, "upper_arm");
+        limb(3, 7, 11, 8, "forearm");
     }
 }
@@ -0,0 +1,80 @@
+// Runs random agents through a level without opening a window,
+// to check the environment and measure how fast it steps.
+//
+//     cargo run --release --bin gym -- assets/level1.tmj [episodes]
+
+use bike::bike::{BikeParams, Direction, Input, WheelInput};
+use bike::env::Env;
+use bike::level::Level;
+
+// xorshift, good enough for picking random actions
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+fn random_action(rng: &mut Rng) -> Input {
+    let r = rng.next();
+    Input {
+        toggle_dir: r.is_multiple_of(20),
+        wheel: match (r >> 8) % 4 {
+            0 => WheelInput::None,
+            1 => WheelInput::Break,
+            _ => WheelInput::Accelerate,
+        },
+        jump: match (r >> 16) % 10 {
+            0 => Some(Direction::Left),
+            1 => Some(Direction::Right),
+            _ => None,
+        },
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let path = args.get(1).map_or("assets/level1.tmj", |s| s.as_str());
+    let episodes: u32 = args.get(2).and_then(|s| s.parse().ok()).unwrap_or(10);
+
+    let level = match std::fs::read(path).and_then(|b| Level::parse_file(path, &b)) {
+        Ok(level) => level,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut env = Env::new(level, BikeParams::default());
+    let mut rng = Rng(0x2545f4914f6cdd1d);
+    let mut steps = 0u64;
+    let start = std::time::Instant::now();
+    for episode in 0..episodes {
+        env.reset();
+        let mut total = 0.0;
+        loop {
+            let step = env.step(&random_action(&mut rng));
+            steps += 1;
+            total += step.reward;
+            if step.done {
+                break;
+            }
+        }
+        println!(
+            "episode {:>3}: reward {:>6.2}  time {:>5.2}s  stars left {}",
+            episode, total, env.time, env.level.stars_left
+        );
+    }
+    let secs = start.elapsed().as_secs_f64();
+    println!(
+        "{} steps in {:.2}s, {:.0} steps/s, {:.1}x real time",
+        steps,
+        secs,
+        steps as f64 / secs,
+        steps as f64 * (env.ticks_per_step as f64 * bike::bike::PHYSICS_DT as f64) / secs
+    );
+}
@@ -0,0 +1,265 @@
+// Plays a replay back into an off-screen render target and writes the frames
+// as an animated GIF or as numbered PNG files, at a fixed frame rate no
+// matter how fast the machine renders.
+//
+//     cargo run --release --bin render -- level1.replay [options]
+//
+//     -o <path>          out.gif for a GIF, anything else is a directory
+//                        for 00000.png, 00001.png, ... (default: <replay>.gif)
+//     --fps <n>          frames per second (default: 30)
+//     --scale <n>        pixels per level unit (default: 1, 480x270)
+//     --hold <secs>      keep showing the end of the run (default: 1)
+//     --levels <dir>     directory with the level files (default: assets)
+//
+// The bike profile is looked up by its hash in assets/bikes.json, the skin by
+// its name in assets/skins.json. macroquad can't run without a window, so a
+// tiny empty one is open while rendering.
+
+use macroquad::prelude::*;
+
+use bike::bike::{load_profiles, Bike, PHYSICS_DT};
+use bike::capture::{Frame, Gif};
+use bike::decoration::Textures;
+use bike::fx;
+use bike::level::Level;
+use bike::materials::{Materials, Theme};
+use bike::particles::Particles;
+use bike::replay::Replay;
+use bike::skin;
+use bike::ui::{self, Align, Ui};
+use bike::LevelTime;
+
+const W: f32 = 480.0;
+const H: f32 = 270.0;
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    std::process::exit(1);
+}
+
+struct Options {
+    replay: String,
+    out: String,
+    fps: f32,
+    scale: f32,
+    hold: f32,
+    levels: String,
+}
+
+fn parse_args() -> Options {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut replay = None;
+    let mut out = None;
+    let mut fps = 30.0;
+    let mut scale = 1.0;
+    let mut hold = 1.0;
+    let mut levels = "assets".to_string();
+
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        let mut value = |name: &str| {
+            it.next()
+                .cloned()
+                .unwrap_or_else(|| fail(&format!("missing value for {}", name)))
+        };
+        let number = |v: String| {
+            v.parse::<f32>()
+                .ok()
+                .filter(|n| *n > 0.0)
+                .unwrap_or_else(|| fail(&format!("invalid number {}", v)))
+        };
+        match arg.as_str() {
+            "-o" => out = Some(value(arg)),
+            "--fps" => fps = number(value(arg)),
+            "--scale" => scale = number(value(arg)),
+            "--hold" => hold = number(value(arg)),
+            "--levels" => levels = value(arg),
+            _ if replay.is_none() => replay = Some(arg.clone()),
+            _ => fail(&format!("unexpected argument {}", arg)),
+        }
+    }
+    let replay = replay.unwrap_or_else(|| fail("usage: render <replay file> [options]"));
+    let out = out.unwrap_or_else(|| {
+        let stem = replay
+            .rsplit_once('.')
+            .map_or(replay.as_str(), |(stem, _)| stem);
+        format!("{}.gif", stem)
+    });
+    Options {
+        replay,
+        out,
+        fps,
+        scale,
+        hold,
+        levels,
+    }
+}
+
+enum Output {
+    Gif(Gif<std::io::BufWriter<std::fs::File>>),
+    Frames(String),
+}
+
+impl Output {
+    fn create(path: &str, frame_size: (u16, u16), fps: f32) -> std::io::Result<Output> {
+        if path.ends_with(".gif") {
+            let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+            Ok(Output::Gif(Gif::new(
+                file,
+                frame_size.0,
+                frame_size.1,
+                fps,
+            )?))
+        } else {
+            std::fs::create_dir_all(path)?;
+            Ok(Output::Frames(path.to_string()))
+        }
+    }
+
+    fn write(&mut self, i: usize, frame: &Frame) -> std::io::Result<()> {
+        match self {
+            Output::Gif(gif) => gif.frame(frame),
+            Output::Frames(dir) => std::fs::write(format!("{}/{:05}.png", dir, i), frame.png()),
+        }
+    }
+
+    fn finish(self) -> std::io::Result<()> {
+        match self {
+            Output::Gif(gif) => gif.finish().map(|_| ()),
+            Output::Frames(_) => Ok(()),
+        }
+    }
+}
+
+fn window_conf() -> Conf {
+    Conf {
+        window_title: "Bike render".to_string(),
+        window_width: 1,
+        window_height: 1,
+        window_resizable: false,
+        // don't wait for the display between frames
+        platform: miniquad::conf::Platform {
+            swap_interval: Some(0),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+#[macroquad::main(window_conf)]
+async fn main() {
+    let options = parse_args();
+    let replay = Replay::load(&options.replay)
+        .unwrap_or_else(|e| fail(&format!("{}: {}", options.replay, e)));
+
+    let path = format!("{}/{}", options.levels, replay.level);
+    let mut level = Level::load(&path)
+        .await
+        .unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+    let profile = load_profiles("assets/bikes.json")
+        .await
+        .unwrap_or_else(|e| fail(&format!("assets/bikes.json: {}", e)))
+        .into_iter()
+        .find(|p| p.params.hash() == replay.profile)
+        .unwrap_or_else(|| fail(&format!("no bike profile {:08x}", replay.profile)));
+    let skins = skin::load_skins("assets/skins.json")
+        .await
+        .unwrap_or_else(|_| vec![Default::default()]);
+    let skin = skin::find(&skins, &replay.skin);
+
+    let mut materials = Materials::load();
+    let mut textures = Textures::default();
+    let pack_theme = match load_string(&format!("{}/theme.json", options.levels)).await {
+        Ok(string) => {
+            let mut theme =
+                Theme::parse_json(&string).unwrap_or_else(|e| fail(&format!("theme.json: {}", e)));
+            theme.resolve_paths(&options.levels);
+            theme
+        }
+        Err(_) => Theme::default(),
+    };
+    let assets = async {
+        textures.load(&level.decorations).await?;
+        textures.load_paths(skin.textures()).await?;
+        materials
+            .set_theme(level.theme.clone().or(&pack_theme))
+            .await
+    };
+    if let Err(e) = assets.await {
+        fail(&e);
+    }
+
+    let size = (
+        (W * options.scale).round() as u16,
+        (H * options.scale).round() as u16,
+    );
+    let target = render_target(size.0 as u32, size.1 as u32);
+    target.texture.set_filter(FilterMode::Nearest);
+    let mut output = Output::create(&options.out, size, options.fps)
+        .unwrap_or_else(|e| fail(&format!("{}: {}", options.out, e)));
+
+    let ui = Ui::with_scale(&materials.font, vec2(W, H), options.scale);
+
+    level.restart();
+    let mut bike = Bike::new(level.start, level.bike_params(profile.params));
+    let mut particles = Particles::default();
+    let mut batch = fx::Batch::default();
+    let inputs: Vec<_> = replay.iter().copied().collect();
+    let mut tick = 0;
+    let frame_dt = 1.0 / options.fps;
+    let frames = ((inputs.len() as f32 * PHYSICS_DT + options.hold) * options.fps).ceil() as usize;
+    for i in 0..frames {
+        // the physics catch up with the frame, then the rest of the world
+        let until = ((i as f32 * frame_dt / PHYSICS_DT).round() as usize).min(inputs.len());
+        while tick < until {
+            bike.update(PHYSICS_DT, &mut level, &inputs[tick]);
+            tick += 1;
+        }
+        level.update(frame_dt);
+        particles.update(frame_dt, &[(&bike, &level)], vec2(W, H), &materials.theme);
+
+        let mut cam = Camera2D::from_display_rect(Rect::new(0.0, 0.0, W, H));
+        cam.zoom.y = cam.zoom.y.abs();
+        cam.target = bike.frame.pos;
+        cam.render_target = Some(target.clone());
+        set_camera(&cam);
+        clear_background(materials.theme.background.unwrap());
+        level.draw_decorations(false, &textures, &cam, &mut batch);
+        level.draw(&materials, cam.target, &mut batch);
+        bike.draw(skin, &textures, &mut batch);
+        particles.draw(&mut batch);
+        level.draw_decorations(true, &textures, &cam, &mut batch);
+
+        cam.target = vec2(W, H) * 0.5;
+        set_camera(&cam);
+        let stars = level.stars.len() - level.stars_left;
+        ui.mono(
+            &format!("{}/{}", stars, level.stars.len()),
+            ui.at(ui::TOP_LEFT, vec2(5.0, 12.0)),
+            Align::Left,
+            &ui.small,
+        );
+        let time = LevelTime::new(tick as f32 * PHYSICS_DT).to_string();
+        let at = ui.at(ui::TOP_RIGHT, vec2(-5.0, 12.0));
+        ui.mono(&time, at, Align::Right, &ui.small);
+
+        // flushes the drawing into the target
+        next_frame().await;
+
+        let frame = Frame::from_image(&target.texture.get_texture_data(), false);
+        output
+            .write(i, &frame)
+            .unwrap_or_else(|e| fail(&format!("{}: {}", options.out, e)));
+        eprint!("\rframe {}/{}", i + 1, frames);
+    }
+    eprintln!();
+    output
+        .finish()
+        .unwrap_or_else(|e| fail(&format!("{}: {}", options.out, e)));
+    println!(
+        "{} ({}) written to {}",
+        options.replay,
+        replay.time(),
+        options.out
+    );
+}
@@ -0,0 +1,246 @@
+// Reference leaderboard server, see src/leaderboard.rs for the protocol.
+//
+//     cargo run --release --bin server -- [options]
+//
+//     --addr <address>   address to listen on (default: 127.0.0.1:8000)
+//     --levels <dir>     directory with the level files (default: assets)
+//     --profiles <file>  bike profiles (default: assets/bikes.json)
+//     --db <file>        where times are stored (default: leaderboard.json)
+
+use std::collections::HashMap;
+
+use bike::bike::{load_profiles_file, BikeParams};
+use bike::leaderboard::{self, hex, parse_hex, Entry};
+use bike::level::Level;
+use bike::replay::Replay;
+use bike::LevelTime;
+
+struct Record {
+    player: String,
+    level: u32,
+    profile: u32,
+    time: LevelTime,
+    replay: String,
+}
+
+struct Server {
+    // level file names and contents by hash
+    levels: HashMap<u32, (String, Vec<u8>)>,
+    profiles: HashMap<u32, BikeParams>,
+    records: Vec<Record>,
+    db: String,
+}
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    std::process::exit(1);
+}
+
+impl Server {
+    fn load_records(&mut self) {
+        let Ok(text) = std::fs::read_to_string(&self.db) else {
+            return;
+        };
+        let json: serde_json::Value =
+            serde_json::from_str(&text).unwrap_or_else(|e| fail(&format!("{}: {}", self.db, e)));
+        for r in json.as_array().into_iter().flatten() {
+            let record = (|| {
+                Some(Record {
+                    player: r["player"].as_str()?.to_string(),
+                    level: parse_hex(r["level"].as_str()?)?,
+                    profile: parse_hex(r["profile"].as_str()?)?,
+                    time: r["time"].as_str()?.parse().ok()?,
+                    replay: r["replay"].as_str()?.to_string(),
+                })
+            })();
+            match record {
+                Some(record) => self.records.push(record),
+                None => fail(&format!("{}: invalid record", self.db)),
+            }
+        }
+    }
+
+    fn save_records(&self) {
+        let json: Vec<serde_json::Value> = self
+            .records
+            .iter()
+            .map(|r| {
+                serde_json::json!({
+                    "player": r.player,
+                    "level": hex(r.level),
+                    "profile": hex(r.profile),
+                    "time": r.time.to_string(),
+                    "replay": r.replay,
+                })
+            })
+            .collect();
+        let text = serde_json::to_string_pretty(&json).unwrap();
+        if let Err(e) = std::fs::write(&self.db, text) {
+            eprintln!("{}: {}", self.db, e);
+        }
+    }
+
+    fn top(&self, level: u32, profile: u32) -> Vec<Entry> {
+        let mut top: Vec<Entry> = self
+            .records
+            .iter()
+            .filter(|r| r.level == level && r.profile == profile)
+            .map(|r| Entry {
+                player: r.player.clone(),
+                time: r.time,
+            })
+            .collect();
+        top.sort_by_key(|e| e.time.0);
+        top
+    }
+
+    fn submit(&mut self, body: &str) -> Result<usize, String> {
+        let json: serde_json::Value = serde_json::from_str(body).map_err(|e| e.to_string())?;
+        let field = |name: &str| {
+            json[name]
+                .as_str()
+                .ok_or_else(|| format!("missing {}", name))
+        };
+        let player = leaderboard::clean_name(field("player")?);
+        let level = parse_hex(field("level")?).ok_or("invalid level hash")?;
+        let profile = parse_hex(field("profile")?).ok_or("invalid profile hash")?;
+        let claimed: LevelTime = field("time")?.parse().map_err(|_| "invalid time")?;
+        let text = field("replay")?;
+        let replay: Replay = text.parse()?;
+
+        let (file, source) = self.levels.get(&level).ok_or("unknown level")?;
+        let params = *self.profiles.get(&profile).ok_or("unknown bike profile")?;
+        let mut level_data = Level::parse_file(file, source).map_err(|e| e.to_string())?;
+        let time = leaderboard::verify(&replay, &mut level_data, params, claimed)?;
+        if time != claimed {
+            return Err(format!("replay takes {}, not {}", time, claimed));
+        }
+
+        let existing = self
+            .records
+            .iter()
+            .position(|r| r.player == player && r.level == level && r.profile == profile);
+        match existing {
+            Some(i) if self.records[i].time <= time => {}
+            _ => {
+                if let Some(i) = existing {
+                    self.records.remove(i);
+                }
+                self.records.push(Record {
+                    player: player.clone(),
+                    level,
+                    profile,
+                    time,
+                    replay: text.to_string(),
+                });
+                self.save_records();
+            }
+        }
+        println!("{} {} {} {}", player, hex(level), hex(profile), time);
+        let top = self.top(level, profile);
+        Ok(top.iter().position(|e| e.player == player).unwrap_or(0) + 1)
+    }
+
+    fn handle(&mut self, request: &leaderboard::Request) -> (u16, serde_json::Value) {
+        let error = |status, msg: &str| (status, serde_json::json!({ "error": msg }));
+        match (request.method.as_str(), request.path.as_str()) {
+            ("POST", "/submit") => match self.submit(&request.body) {
+                Ok(rank) => (200, serde_json::json!({ "rank": rank })),
+                Err(e) => error(400, &e),
+            },
+            ("GET", "/top") => {
+                let hash = |name: &str| request.query.get(name).and_then(|h| parse_hex(h));
+                let (Some(level), Some(profile)) = (hash("level"), hash("profile")) else {
+                    return error(400, "level and profile hashes required");
+                };
+                let count = request
+                    .query
+                    .get("count")
+                    .and_then(|c| c.parse().ok())
+                    .unwrap_or(leaderboard::TOP_COUNT);
+                let times: Vec<serde_json::Value> = self
+                    .top(level, profile)
+                    .iter()
+                    .take(count)
+                    .map(|e| serde_json::json!({ "player": e.player, "time": e.time.to_string() }))
+                    .collect();
+                (200, serde_json::json!({ "times": times }))
+            }
+            _ => error(404, "not found"),
+        }
+    }
+}
+
+fn main() {
+    let mut addr = "127.0.0.1:8000".to_string();
+    let mut levels_dir = "assets".to_string();
+    let mut profiles_file = "assets/bikes.json".to_string();
+    let mut db = "leaderboard.json".to_string();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        let value = it
+            .next()
+            .cloned()
+            .unwrap_or_else(|| fail(&format!("missing value for {}", arg)));
+        match arg.as_str() {
+            "--addr" => addr = value,
+            "--levels" => levels_dir = value,
+            "--profiles" => profiles_file = value,
+            "--db" => db = value,
+            _ => fail(&format!("unexpected argument {}", arg)),
+        }
+    }
+
+    let mut server = Server {
+        levels: HashMap::new(),
+        profiles: HashMap::new(),
+        records: vec![],
+        db,
+    };
+    let dir =
+        std::fs::read_dir(&levels_dir).unwrap_or_else(|e| fail(&format!("{}: {}", levels_dir, e)));
+    for entry in dir.flatten() {
+        let path = entry.path();
+        if path
+            .extension()
+            .is_some_and(|e| e == "tmj" || e == "lev" || e == "svg")
+        {
+            if let Ok(source) = std::fs::read(&path) {
+                let hash = bike::fnv1a(&source);
+                let file = path.to_string_lossy().to_string();
+                println!("level {} {}", hex(hash), file);
+                server.levels.insert(hash, (file, source));
+            }
+        }
+    }
+    let profiles = load_profiles_file(&profiles_file)
+        .unwrap_or_else(|e| fail(&format!("{}: {}", profiles_file, e)));
+    for p in profiles {
+        println!("profile {} {}", hex(p.params.hash()), p.name);
+        server.profiles.insert(p.params.hash(), p.params);
+    }
+    server.load_records();
+
+    let listener =
+        std::net::TcpListener::bind(&addr).unwrap_or_else(|e| fail(&format!("{}: {}", addr, e)));
+    println!("listening on {}", addr);
+    for stream in listener.incoming() {
+        let Ok(mut stream) = stream else {
+            continue;
+        };
+        // clients that don't read their answer don't hold up the others
+        let _ = stream.set_write_timeout(Some(std::time::Duration::from_secs(10)));
+        match leaderboard::read_request(&mut stream) {
+            Ok(request) => {
+                let (status, json) = server.handle(&request);
+                leaderboard::write_response(&mut stream, status, &json);
+            }
+            Err((status, e)) => {
+                eprintln!("{}", e);
+                let json = serde_json::json!({ "error": e });
+                leaderboard::write_response(&mut stream, status, &json);
+            }
+        }
+    }
+}
@@ -0,0 +1,112 @@
+// Searches a level for a run that collects all stars and writes it as a replay.
+//
+//     cargo run --release --bin solver -- assets/level1.tmj [options]
+//
+//     --profile <name>   bike profile from assets/bikes.json (default: the first one)
+//     --beam <n>         runs kept per segment (default: 64)
+//     --segment <ticks>  physics ticks each input is held for (default: 500)
+//     --max-time <secs>  give up after this much level time (default: 60)
+//     -o <file>          replay file (default: <level>.replay)
+
+use bike::bike::{load_profiles_file, BikeParams};
+use bike::level::Level;
+use bike::solver::{solve, Config};
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    std::process::exit(1);
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut path = None;
+    let mut profile = None;
+    let mut out = None;
+    let mut config = Config::default();
+
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        let mut value = |name: &str| {
+            it.next()
+                .cloned()
+                .unwrap_or_else(|| fail(&format!("missing value for {}", name)))
+        };
+        let number = |v: String| {
+            v.parse::<f32>()
+                .ok()
+                .filter(|n| *n > 0.0)
+                .unwrap_or_else(|| fail(&format!("invalid number {}", v)))
+        };
+        let count = |v: String| {
+            v.parse::<u32>()
+                .ok()
+                .filter(|n| *n >= 1)
+                .unwrap_or_else(|| fail(&format!("invalid count {}", v)))
+        };
+        match arg.as_str() {
+            "--profile" => profile = Some(value(arg)),
+            "--beam" => config.beam_width = count(value(arg)) as usize,
+            "--segment" => config.segment_ticks = count(value(arg)),
+            "--max-time" => config.max_time = number(value(arg)),
+            "-o" => out = Some(value(arg)),
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => fail(&format!("unexpected argument {}", arg)),
+        }
+    }
+    let path = path.unwrap_or_else(|| fail("usage: solver <level file> [options]"));
+
+    let params = match profile {
+        None => load_profiles_file("assets/bikes.json")
+            .ok()
+            .and_then(|p| p.into_iter().next())
+            .map_or(BikeParams::default(), |p| p.params),
+        Some(name) => {
+            load_profiles_file("assets/bikes.json")
+                .unwrap_or_else(|e| fail(&format!("assets/bikes.json: {}", e)))
+                .into_iter()
+                .find(|p| p.name == name)
+                .unwrap_or_else(|| fail(&format!("unknown profile {}", name)))
+                .params
+        }
+    };
+
+    let source = std::fs::read(&path).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+    let file = std::path::Path::new(&path)
+        .file_name()
+        .map_or(path.clone(), |f| f.to_string_lossy().to_string());
+
+    let start = std::time::Instant::now();
+    let result = solve(&source, &file, params, &config, |time, stars| {
+        eprint!("\rsearched {:>6.2}s, {} stars", time, stars);
+    });
+    eprintln!();
+    let replay = match result {
+        Ok(Some(replay)) => replay,
+        Ok(None) => fail("no run found that collects all stars"),
+        Err(e) => fail(&format!("{}: {}", path, e)),
+    };
+
+    // the replay has to hold up when played back on its own
+    let mut level =
+        Level::parse_file(&file, &source).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+    let params = level.bike_params(params);
+    if replay.run(&mut level, params, replay.ticks()) != Some(replay.ticks()) {
+        fail("the replay does not reproduce the run");
+    }
+
+    let out = out.unwrap_or_else(|| {
+        let stem = file
+            .rsplit_once('.')
+            .map_or(file.as_str(), |(stem, _)| stem);
+        format!("{}.replay", stem)
+    });
+    if let Err(e) = replay.save(&out) {
+        fail(&format!("{}: {}", out, e));
+    }
+    println!(
+        "best time {} ({:.1}s search), replay written to {}",
+        replay.time(),
+        start.elapsed().as_secs_f32(),
+        out
+    );
+}
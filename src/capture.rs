@@ -0,0 +1,269 @@
+// Screenshots and recordings of runs.
+//
+// Frames are RGBA images with the top row first. They are written as PNG
+// files, uncompressed but readable anywhere, or as animated GIFs with a
+// palette of up to 256 colors per frame, picked from the most common colors
+// of the frame.
+
+use macroquad::prelude::*;
+use std::collections::HashMap;
+use std::io::Write;
+
+pub struct Frame {
+    pub width: u16,
+    pub height: u16,
+    pub bytes: Vec<u8>,
+}
+
+impl Frame {
+    // OpenGL reads images bottom up, render targets drawn with a camera
+    // of positive zoom.y come out top down
+    pub fn from_image(image: &Image, bottom_up: bool) -> Frame {
+        let row = image.width as usize * 4;
+        let bytes = if bottom_up {
+            image.bytes.chunks(row).rev().flatten().copied().collect()
+        } else {
+            image.bytes.clone()
+        };
+        Frame {
+            width: image.width,
+            height: image.height,
+            bytes,
+        }
+    }
+
+    pub fn png(&self) -> Vec<u8> {
+        let row = self.width as usize * 4;
+        // every row starts with its filter type, 0 is none
+        let mut raw = Vec::with_capacity((row + 1) * self.height as usize);
+        for line in self.bytes.chunks(row) {
+            raw.push(0);
+            raw.extend_from_slice(line);
+        }
+
+        // zlib stream of stored deflate blocks
+        let mut zlib = vec![0x78, 0x01];
+        let mut blocks = raw.chunks(0xffff).peekable();
+        while let Some(block) = blocks.next() {
+            let len = block.len() as u16;
+            zlib.push(blocks.peek().is_none() as u8);
+            zlib.extend_from_slice(&len.to_le_bytes());
+            zlib.extend_from_slice(&(!len).to_le_bytes());
+            zlib.extend_from_slice(block);
+        }
+        zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+        let mut header = vec![];
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        // 8 bits per channel, RGBA, deflate, no filters, not interlaced
+        header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        for (tpe, data) in [(b"IHDR", &header), (b"IDAT", &zlib), (b"IEND", &vec![])] {
+            png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            let start = png.len();
+            png.extend_from_slice(tpe);
+            png.extend_from_slice(data);
+            let crc = crc32(&png[start..]);
+            png.extend_from_slice(&crc.to_be_bytes());
+        }
+        png
+    }
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (a, b) = bytes.iter().fold((1u32, 0u32), |(a, b), x| {
+        let a = (a + *x as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+    (b << 16) | a
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(0xffffffff, |crc: u32, x| {
+        (0..8).fold(crc ^ *x as u32, |c, _| {
+            if c & 1 != 0 {
+                (c >> 1) ^ 0xedb88320
+            } else {
+                c >> 1
+            }
+        })
+    })
+}
+
+// what is on the screen right now, call it after drawing the frame
+pub fn screenshot() -> Frame {
+    Frame::from_image(&get_screen_data(), true)
+}
+
+// the first of screenshot-000.png, screenshot-001.png, ... that doesn't exist
+#[cfg(not(target_arch = "wasm32"))]
+pub fn screenshot_path() -> String {
+    (0..)
+        .map(|i| format!("screenshot-{:03}.png", i))
+        .find(|p| !std::path::Path::new(p).exists())
+        .unwrap()
+}
+
+// Animated GIF, looping forever.
+pub struct Gif<W: Write> {
+    out: W,
+    width: u16,
+    height: u16,
+    fps: f32,
+    frames: u32,
+}
+
+impl<W: Write> Gif<W> {
+    pub fn new(mut out: W, width: u16, height: u16, fps: f32) -> std::io::Result<Self> {
+        out.write_all(b"GIF89a")?;
+        out.write_all(&width.to_le_bytes())?;
+        out.write_all(&height.to_le_bytes())?;
+        // no global palette, every frame has its own
+        out.write_all(&[0, 0, 0])?;
+        // loop forever
+        out.write_all(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+        Ok(Gif {
+            out,
+            width,
+            height,
+            fps,
+            frames: 0,
+        })
+    }
+
+    pub fn frame(&mut self, frame: &Frame) -> std::io::Result<()> {
+        assert!(frame.width == self.width && frame.height == self.height);
+        let (palette, indices) = quantize(&frame.bytes);
+
+        // delays are in hundredths of a second, rounded so they add up
+        let cs = |n: u32| (n as f32 * 100.0 / self.fps).round() as u16;
+        let delay = cs(self.frames + 1) - cs(self.frames);
+        self.frames += 1;
+        self.out.write_all(&[0x21, 0xf9, 0x04, 0x00])?;
+        self.out.write_all(&delay.to_le_bytes())?;
+        self.out.write_all(&[0x00, 0x00])?;
+
+        // image descriptor with a local palette of 256 colors
+        self.out.write_all(&[0x2c, 0, 0, 0, 0])?;
+        self.out.write_all(&self.width.to_le_bytes())?;
+        self.out.write_all(&self.height.to_le_bytes())?;
+        self.out.write_all(&[0x87])?;
+        for i in 0..256 {
+            self.out
+                .write_all(&palette.get(i).copied().unwrap_or([0, 0, 0]))?;
+        }
+
+        self.out.write_all(&[8])?;
+        for block in lzw(&indices).chunks(255) {
+            self.out.write_all(&[block.len() as u8])?;
+            self.out.write_all(block)?;
+        }
+        self.out.write_all(&[0])
+    }
+
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.out.write_all(&[0x3b])?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+// Up to 256 colors for the pixels and the index of each pixel. Colors are
+// grouped by their top 5 bits per channel, the most common groups make the
+// palette with their average color and the others get the nearest entry.
+fn quantize(rgba: &[u8]) -> (Vec<[u8; 3]>, Vec<u8>) {
+    let key =
+        |p: &[u8]| (p[0] as usize >> 3) << 10 | (p[1] as usize >> 3) << 5 | p[2] as usize >> 3;
+    let mut groups: HashMap<usize, (u32, [u32; 3])> = HashMap::new();
+    for p in rgba.chunks(4) {
+        let (n, sum) = groups.entry(key(p)).or_default();
+        *n += 1;
+        for c in 0..3 {
+            sum[c] += p[c] as u32;
+        }
+    }
+    let mut by_count: Vec<_> = groups.into_iter().collect();
+    by_count.sort_by_key(|(k, (n, _))| (std::cmp::Reverse(*n), *k));
+
+    let mut palette = vec![];
+    let mut index = HashMap::new();
+    for (k, (n, sum)) in by_count.iter() {
+        if palette.len() < 256 {
+            index.insert(*k, palette.len() as u8);
+            palette.push(sum.map(|s| (s / n) as u8));
+        } else {
+            let color = sum.map(|s| (s / n) as i32);
+            let nearest = palette
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, p)| (0..3).map(|c| (p[c] as i32 - color[c]).pow(2)).sum::<i32>())
+                .unwrap()
+                .0;
+            index.insert(*k, nearest as u8);
+        }
+    }
+    let indices = rgba.chunks(4).map(|p| index[&key(p)]).collect();
+    (palette, indices)
+}
+
+// GIF flavored LZW with 8 bit symbols, codes of up to 12 bits packed from
+// the lowest bit on.
+fn lzw(indices: &[u8]) -> Vec<u8> {
+    const CLEAR: u16 = 256;
+    const END: u16 = 257;
+
+    let mut out = vec![];
+    let mut bits = 0u32;
+    let mut n_bits = 0;
+    let mut emit = |code: u16, size: u32, out: &mut Vec<u8>| {
+        bits |= (code as u32) << n_bits;
+        n_bits += size;
+        while n_bits >= 8 {
+            out.push(bits as u8);
+            bits >>= 8;
+            n_bits -= 8;
+        }
+    };
+
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = END + 1;
+    let mut size = 9;
+    emit(CLEAR, size, &mut out);
+    if let Some((&first, rest)) = indices.split_first() {
+        let mut prefix = first as u16;
+        for &k in rest {
+            if let Some(&code) = table.get(&(prefix, k)) {
+                prefix = code;
+                continue;
+            }
+            emit(prefix, size, &mut out);
+            if next == 4096 {
+                emit(CLEAR, size, &mut out);
+                table.clear();
+                next = END + 1;
+                size = 9;
+            } else {
+                // the decoder is one code behind, so grow before adding the
+                // first code that needs another bit
+                if next >= 1 << size {
+                    size += 1;
+                }
+                table.insert((prefix, k), next);
+                next += 1;
+            }
+            prefix = k as u16;
+        }
+        emit(prefix, size, &mut out);
+        // the decoder adds a code for the last prefix too
+        if next >= 1 << size && size < 12 {
+            size += 1;
+        }
+    }
+    emit(END, size, &mut out);
+    if n_bits > 0 {
+        out.push(bits as u8);
+    }
+    out
+}
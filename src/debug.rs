@@ -0,0 +1,123 @@
+use macroquad::prelude::*;
+use std::collections::VecDeque;
+
+use crate::bike::{Bike, HEAD_R};
+use crate::level::Level;
+use crate::ui::{Align, Ui, BOTTOM_LEFT, BOTTOM_RIGHT};
+
+const HISTORY: usize = 240;
+const GRAPH_RANGE: f32 = 80.0;
+const FORCE_SCALE: f32 = 0.005;
+const VEL_SCALE: f32 = 0.1;
+const NORMAL_LENGTH: f32 = 10.0;
+
+const WHEEL_COLORS: [Color; 2] = [
+    Color::new(0.3, 0.7, 1.0, 1.0),
+    Color::new(1.0, 0.6, 0.2, 1.0),
+];
+
+#[derive(Default)]
+pub struct DebugOverlay {
+    pub enabled: bool,
+    // physics steps of the last frame
+    steps: u32,
+    // wheel angular velocities, one entry per frame
+    history: VecDeque<[f32; 2]>,
+}
+
+fn arrow(p: Vec2, v: Vec2, color: Color) {
+    let q = p + v;
+    draw_line(p.x, p.y, q.x, q.y, 0.5, color);
+    if v.length_squared() > 4.0 {
+        let d = v.normalize() * 2.0;
+        for s in [d.perp(), -d.perp()] {
+            let r = q - d + s * 0.5;
+            draw_line(q.x, q.y, r.x, r.y, 0.5, color);
+        }
+    }
+}
+
+impl DebugOverlay {
+    pub fn record(&mut self, steps: u32, bike: &Bike) {
+        self.steps = steps;
+        self.history
+            .push_back(bike.wheels.each_ref().map(|w| w.ang_vel));
+        if self.history.len() > HISTORY {
+            self.history.pop_front();
+        }
+    }
+
+    // collision shapes and forces in world space
+    pub fn draw_world(&self, bike: &Bike, level: &Level) {
+        level.draw_wireframe(Color::new(1.0, 1.0, 1.0, 0.2));
+
+        let white = Color::new(1.0, 1.0, 1.0, 0.8);
+        let head = bike.head();
+        draw_circle_lines(head.x, head.y, HEAD_R, 0.5, white);
+
+        for (i, wheel) in bike.wheels.iter().enumerate() {
+            let c = WHEEL_COLORS[i];
+            draw_circle_lines(wheel.pos.x, wheel.pos.y, bike.params.wheel_r, 0.5, c);
+            arrow(wheel.pos, wheel.vel * VEL_SCALE, GREEN);
+            arrow(wheel.pos, -bike.spring_forces[i] * FORCE_SCALE, c);
+            if let Some(ci) = bike.contacts[i] {
+                let p = wheel.pos - ci.normal * bike.params.wheel_r;
+                arrow(p, ci.normal * NORMAL_LENGTH, RED);
+            }
+        }
+        arrow(bike.frame.pos, bike.frame.vel * VEL_SCALE, GREEN);
+    }
+
+    pub fn draw_hud(&self, bike: &Bike, ui: &Ui) {
+        let pen = bike.contacts.map(|c| c.map_or(0.0, |c| c.dist));
+        let lines = [
+            format!("STEPS {}  FPS {}", self.steps, get_fps()),
+            format!("SPEED {:.1}", bike.frame.vel.length()),
+            format!("PENETRATION {:.3} {:.3}", pen[0], pen[1]),
+            format!(
+                "WHEEL ANG VEL {:.1} {:.1}",
+                bike.wheels[0].ang_vel, bike.wheels[1].ang_vel
+            ),
+        ];
+        for (i, line) in lines.iter().enumerate() {
+            let at = ui.at(BOTTOM_LEFT, vec2(5.0, -70.0 + i as f32 * 12.0));
+            ui.text(line, at, Align::Left, &ui.small);
+        }
+
+        // angular velocity graph
+        let size = vec2(HISTORY as f32 * 0.5, 50.0);
+        let origin = ui.at(BOTTOM_RIGHT, -size - vec2(5.0, 5.0));
+        draw_rectangle(
+            origin.x,
+            origin.y,
+            size.x,
+            size.y,
+            Color::new(0.0, 0.0, 0.0, 0.5),
+        );
+        let mid = origin.y + size.y * 0.5;
+        draw_line(
+            origin.x,
+            mid,
+            origin.x + size.x,
+            mid,
+            0.5,
+            Color::new(1.0, 1.0, 1.0, 0.3),
+        );
+        let point = |i: usize, v: f32| {
+            let y = (v / GRAPH_RANGE).clamp(-1.0, 1.0) * size.y * 0.5;
+            vec2(origin.x + i as f32 * 0.5, mid - y)
+        };
+        for (i, pair) in self
+            .history
+            .iter()
+            .zip(self.history.iter().skip(1))
+            .enumerate()
+        {
+            for (w, c) in WHEEL_COLORS.iter().enumerate() {
+                let p = point(i, pair.0[w]);
+                let q = point(i + 1, pair.1[w]);
+                draw_line(p.x, p.y, q.x, q.y, 0.5, *c);
+            }
+        }
+    }
+}
@@ -0,0 +1,331 @@
+// Level art that doesn't collide: filled polygons (like grass), Tiled image
+// layers and tile layers.
+//
+// Decorations come from Tiled layers: object layers named "decoration",
+// image layers and tile layers. Layers that come after the "walls" layer are
+// drawn in front of the level, the others behind it. Tiled's parallax factors
+// are used as they are: 1 moves with the level, 0 stays on screen, and
+// everything in between scrolls slower, relative to the map's parallax origin.
+// Hidden layers are skipped, opacity and tint colors apply.
+//
+// Textures are loaded separately by the game, so headless tools can read
+// levels with decorations without a window.
+
+use std::collections::HashMap;
+
+use macroquad::prelude::*;
+
+use crate::fx;
+use crate::level::invalid_data;
+
+pub const GRASS_COLOR: Color = Color::new(0.25, 0.55, 0.2, 1.0);
+
+// tile ids carry flip flags in the highest bits
+const FLIP_X: u32 = 0x80000000;
+const FLIP_Y: u32 = 0x40000000;
+const TILE_ID_MASK: u32 = 0x0fffffff;
+
+pub struct Tile {
+    // index into the images of the tile layer
+    pub image: usize,
+    pub source: Rect,
+    pub pos: Vec2,
+    pub flip_x: bool,
+    pub flip_y: bool,
+}
+
+pub enum Art {
+    // filled polygons in level coordinates
+    Polygons(fx::Batch),
+    Image {
+        path: String,
+        pos: Vec2,
+        repeat_x: bool,
+        repeat_y: bool,
+    },
+    Tiles {
+        images: Vec<String>,
+        tiles: Vec<Tile>,
+    },
+}
+
+pub struct Decoration {
+    pub art: Art,
+    pub parallax: Vec2,
+    pub front: bool,
+    pub tint: Color,
+}
+
+// triangulated polygons with one color each
+pub fn polygons_batch(polygons: Vec<(Vec<Vec2>, Color)>) -> fx::Batch {
+    let mut batch = fx::Batch::default();
+    for (points, color) in polygons {
+        batch.polygon(&points, color);
+    }
+    batch
+}
+
+impl Decoration {
+    // settings shared by all kinds of Tiled layers
+    pub(crate) fn from_layer(layer: &serde_json::Value, art: Art, front: bool) -> Decoration {
+        let f = |name: &str| layer[name].as_f64().unwrap_or(1.0) as f32;
+        let mut tint = layer["tintcolor"]
+            .as_str()
+            .and_then(crate::properties::parse_color)
+            .unwrap_or(WHITE);
+        tint.a *= f("opacity");
+        Decoration {
+            art,
+            parallax: vec2(f("parallaxx"), f("parallaxy")),
+            front,
+            tint,
+        }
+    }
+
+    fn draw(&self, textures: &Textures, view: Rect) {
+        match &self.art {
+            Art::Polygons(batch) => {
+                if self.tint == WHITE {
+                    batch.draw();
+                } else {
+                    batch.tinted(self.tint).draw();
+                }
+            }
+            Art::Image {
+                path,
+                pos,
+                repeat_x,
+                repeat_y,
+            } => {
+                let Some(texture) = textures.get(path) else {
+                    return;
+                };
+                let size = texture.size();
+                // cover the view with copies of the image along repeated axes
+                let range = |repeat: bool, start: f32, len: f32, view_start: f32, view_len: f32| {
+                    if repeat {
+                        let first = ((view_start - start) / len).floor() as i32;
+                        let last = ((view_start + view_len - start) / len).ceil() as i32;
+                        first..last
+                    } else {
+                        0..1
+                    }
+                };
+                for i in range(*repeat_x, pos.x, size.x, view.x, view.w) {
+                    for j in range(*repeat_y, pos.y, size.y, view.y, view.h) {
+                        let p = *pos + vec2(i as f32, j as f32) * size;
+                        draw_texture(texture, p.x, p.y, self.tint);
+                    }
+                }
+            }
+            Art::Tiles { images, tiles } => {
+                for tile in tiles {
+                    let Some(texture) = textures.get(&images[tile.image]) else {
+                        continue;
+                    };
+                    let r = tile.source;
+                    if !Rect::new(tile.pos.x, tile.pos.y, r.w, r.h).overlaps(&view) {
+                        continue;
+                    }
+                    let params = DrawTextureParams {
+                        source: Some(r),
+                        flip_x: tile.flip_x,
+                        flip_y: tile.flip_y,
+                        ..Default::default()
+                    };
+                    draw_texture_ex(texture, tile.pos.x, tile.pos.y, self.tint, params);
+                }
+            }
+        }
+    }
+
+    pub fn images(&self) -> Vec<&str> {
+        match &self.art {
+            Art::Polygons(_) => vec![],
+            Art::Image { path, .. } => vec![path],
+            Art::Tiles { images, .. } => images.iter().map(|s| s.as_str()).collect(),
+        }
+    }
+
+    pub(crate) fn resolve_paths(&mut self, dir: &str) {
+        let resolve = |path: &mut String| {
+            if !dir.is_empty() && !path.starts_with('/') {
+                *path = format!("{}/{}", dir, path);
+            }
+        };
+        match &mut self.art {
+            Art::Polygons(_) => {}
+            Art::Image { path, .. } => resolve(path),
+            Art::Tiles { images, .. } => images.iter_mut().for_each(resolve),
+        }
+    }
+}
+
+// an image layer, None without an image
+pub(crate) fn image_layer(layer: &serde_json::Value, offset: Vec2) -> Option<Art> {
+    let path = layer["image"].as_str().filter(|p| !p.is_empty())?;
+    Some(Art::Image {
+        path: path.to_string(),
+        pos: offset,
+        repeat_x: layer["repeatx"] == true,
+        repeat_y: layer["repeaty"] == true,
+    })
+}
+
+struct Tileset {
+    first_gid: u32,
+    // None for external and image collection tilesets, which aren't supported
+    image: Option<usize>,
+    tile_size: Vec2,
+    columns: u32,
+    margin: f32,
+    spacing: f32,
+}
+
+// a tile layer, with the tiles of all chunks for infinite maps
+pub(crate) fn tile_layer(
+    map: &serde_json::Value,
+    layer: &serde_json::Value,
+    offset: Vec2,
+) -> Result<Art, std::io::Error> {
+    let mut images = vec![];
+    let mut tilesets = vec![];
+    for t in map["tilesets"].as_array().into_iter().flatten() {
+        // unsupported tilesets are kept so that their tiles aren't taken
+        // from the tileset before them
+        let image = t["image"].as_str().map(|image| {
+            images.push(image.to_string());
+            images.len() - 1
+        });
+        let num = |name: &str| t[name].as_f64().unwrap_or(0.0) as f32;
+        tilesets.push(Tileset {
+            first_gid: t["firstgid"].as_u64().unwrap_or(1) as u32,
+            image,
+            tile_size: vec2(num("tilewidth"), num("tileheight")),
+            columns: t["columns"].as_u64().unwrap_or(1).max(1) as u32,
+            margin: num("margin"),
+            spacing: num("spacing"),
+        });
+    }
+    tilesets.sort_by_key(|t| t.first_gid);
+    let map_tile = vec2(
+        map["tilewidth"].as_f64().unwrap_or(0.0) as f32,
+        map["tileheight"].as_f64().unwrap_or(0.0) as f32,
+    );
+
+    if layer["encoding"].as_str().is_some_and(|e| e != "csv") {
+        return Err(invalid_data("only uncompressed tile layers are supported"));
+    }
+    let mut chunks = vec![layer];
+    chunks.extend(layer["chunks"].as_array().into_iter().flatten());
+
+    let mut tiles = vec![];
+    for chunk in chunks {
+        let Some(data) = chunk["data"].as_array() else {
+            continue;
+        };
+        let width = chunk["width"].as_u64().unwrap_or(0).max(1) as usize;
+        let origin = vec2(
+            chunk["x"].as_f64().unwrap_or(0.0) as f32,
+            chunk["y"].as_f64().unwrap_or(0.0) as f32,
+        );
+        for (i, gid) in data.iter().enumerate() {
+            let gid = gid.as_u64().unwrap_or(0) as u32;
+            let id = gid & TILE_ID_MASK;
+            let Some(set) = tilesets.iter().rev().find(|t| t.first_gid <= id) else {
+                continue;
+            };
+            let Some(image) = set.image else {
+                continue;
+            };
+            if id == 0 {
+                continue;
+            }
+            let index = id - set.first_gid;
+            let (col, row) = ((index % set.columns) as f32, (index / set.columns) as f32);
+            let source = Rect::new(
+                set.margin + col * (set.tile_size.x + set.spacing),
+                set.margin + row * (set.tile_size.y + set.spacing),
+                set.tile_size.x,
+                set.tile_size.y,
+            );
+            let cell = origin + vec2((i % width) as f32, (i / width) as f32);
+            // tiles larger than the grid stick out at the top
+            let pos = offset + cell * map_tile + vec2(0.0, map_tile.y - set.tile_size.y);
+            tiles.push(Tile {
+                image,
+                source,
+                pos,
+                flip_x: gid & FLIP_X != 0,
+                flip_y: gid & FLIP_Y != 0,
+            });
+        }
+    }
+    Ok(Art::Tiles { images, tiles })
+}
+
+// The view rectangle a camera shows, in level coordinates.
+fn camera_view(camera: &Camera2D) -> Rect {
+    let half = vec2(1.0 / camera.zoom.x, 1.0 / camera.zoom.y).abs();
+    Rect::new(
+        camera.target.x - half.x,
+        camera.target.y - half.y,
+        half.x * 2.0,
+        half.y * 2.0,
+    )
+}
+
+// Draws the decorations in front of or behind the level through the given
+// camera, which is set again afterwards.
+pub fn draw(
+    decorations: &[Decoration],
+    front: bool,
+    parallax_origin: Vec2,
+    textures: &Textures,
+    camera: &Camera2D,
+) {
+    for d in decorations.iter().filter(|d| d.front == front) {
+        // a layer moving slower is shifted along with the camera
+        let cam = Camera2D {
+            target: camera.target - (Vec2::ONE - d.parallax) * (camera.target - parallax_origin),
+            render_target: camera.render_target.clone(),
+            ..*camera
+        };
+        set_camera(&cam);
+        d.draw(textures, camera_view(&cam));
+    }
+    set_camera(camera);
+}
+
+// Textures of decorations by path.
+#[derive(Default)]
+pub struct Textures(HashMap<String, Texture2D>);
+
+impl Textures {
+    pub fn get(&self, path: &str) -> Option<&Texture2D> {
+        self.0.get(path)
+    }
+
+    // loads the images that aren't loaded yet
+    pub async fn load(&mut self, decorations: &[Decoration]) -> Result<(), String> {
+        self.load_paths(decorations.iter().flat_map(|d| d.images()))
+            .await
+    }
+
+    pub async fn load_paths(
+        &mut self,
+        paths: impl IntoIterator<Item = &str>,
+    ) -> Result<(), String> {
+        for path in paths {
+            if self.0.contains_key(path) {
+                continue;
+            }
+            let texture = load_texture(path)
+                .await
+                .map_err(|e| format!("{}: {}", path, e))?;
+            texture.set_filter(FilterMode::Nearest);
+            self.0.insert(path.to_string(), texture);
+        }
+        Ok(())
+    }
+}
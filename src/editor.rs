@@ -0,0 +1,722 @@
+use macroquad::prelude::*;
+use std::f32::consts::PI;
+
+use crate::decoration::Textures;
+use crate::fx;
+use crate::level::{CurveKind, Level, PolygonType, SMOOTH_STEP};
+use crate::materials::Materials;
+use crate::properties::{Properties, Property};
+use crate::ui::{Align, Ui, BOTTOM_LEFT, TOP_LEFT};
+
+const GRID: f32 = 16.0;
+// in screen pixels of the unzoomed canvas
+const PICK_RADIUS: f32 = 6.0;
+const UNDO_LIMIT: usize = 200;
+const PAN_SPEED: f32 = 400.0;
+
+#[derive(PartialEq, Clone, Copy)]
+enum Tool {
+    Wall,
+    Lava,
+    Star,
+    Start,
+}
+
+#[derive(Clone)]
+struct Shape {
+    tpe: PolygonType,
+    // the curve's vertices for smoothed shapes
+    points: Vec<Vec2>,
+    smooth: Option<(CurveKind, f32)>,
+    properties: Properties,
+}
+
+#[derive(Clone)]
+struct Data {
+    shapes: Vec<Shape>,
+    start: Vec2,
+    stars: Vec<Vec2>,
+}
+
+#[derive(PartialEq, Clone, Copy)]
+enum Handle {
+    Vertex(usize, usize),
+    Star(usize),
+    Start,
+}
+
+enum Drag {
+    Handle(Handle),
+    Pan(Vec2),
+}
+
+pub struct Editor {
+    pub path: String,
+    // the file the level was loaded from, which keeps everything the editor doesn't touch
+    json: serde_json::Value,
+    data: Data,
+    undo: Vec<Data>,
+    redo: Vec<Data>,
+    // preview built from the edited data
+    level: Level,
+    tool: Tool,
+    snap: bool,
+    view: Vec2,
+    zoom: f32,
+    mouse: Vec2,
+    hover: Option<Handle>,
+    drawing: Vec<Vec2>,
+    drag: Option<Drag>,
+    message: String,
+    // edits since the last save, and whether leaving was asked for once
+    unsaved: bool,
+    leaving: bool,
+}
+
+fn point_in_polygon(p: Vec2, points: &[Vec2]) -> bool {
+    let mut inside = false;
+    for (i, a) in points.iter().enumerate() {
+        let b = points[(i + 1) % points.len()];
+        if (a.y > p.y) != (b.y > p.y) && p.x < a.x + (b.x - a.x) * (p.y - a.y) / (b.y - a.y) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+fn project_on_segment(p: Vec2, a: Vec2, b: Vec2) -> Vec2 {
+    let ab = b - a;
+    let t = ((p - a).dot(ab) / ab.length_squared()).clamp(0.0, 1.0);
+    a + ab * t
+}
+
+fn json_point(p: Vec2) -> serde_json::Value {
+    serde_json::json!({ "x": p.x, "y": p.y })
+}
+
+// Puts the objects into the first layer with the given name, also inside
+// group layers, and empties the other layers of that name. Objects are moved
+// against the layer offsets, unknown objects in the "objects" layers are kept.
+fn replace_objects(
+    layers: &mut [serde_json::Value],
+    name: &str,
+    offset: Vec2,
+    objects: &mut Option<Vec<serde_json::Value>>,
+) {
+    for layer in layers.iter_mut() {
+        let offset = offset
+            + vec2(
+                layer["offsetx"].as_f64().unwrap_or(0.0) as f32,
+                layer["offsety"].as_f64().unwrap_or(0.0) as f32,
+            );
+        if layer["type"] == "group" {
+            if let Some(children) = layer["layers"].as_array_mut() {
+                replace_objects(children, name, offset, objects);
+            }
+            continue;
+        }
+        if layer["name"] != name {
+            continue;
+        }
+        let mut kept: Vec<serde_json::Value> = match layer["objects"].as_array() {
+            Some(objects) if name == "objects" => objects
+                .iter()
+                .filter(|o| o["name"] != "start" && o["name"] != "star")
+                .cloned()
+                .collect(),
+            _ => vec![],
+        };
+        for mut o in objects.take().into_iter().flatten() {
+            o["x"] = (o["x"].as_f64().unwrap_or(0.0) - offset.x as f64).into();
+            o["y"] = (o["y"].as_f64().unwrap_or(0.0) - offset.y as f64).into();
+            kept.push(o);
+        }
+        layer["objects"] = serde_json::Value::Array(kept);
+    }
+}
+
+impl Editor {
+    pub async fn load(path: &str) -> Result<Editor, std::io::Error> {
+        let string = macroquad::file::load_string(path)
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::NotFound, e.to_string()))?;
+        Editor::parse(path, &string)
+    }
+
+    pub fn parse(path: &str, string: &str) -> Result<Editor, std::io::Error> {
+        let json = serde_json::from_str(string)?;
+        let level = Level::parse_file(path, string.as_bytes())?;
+        let data = Data {
+            shapes: level
+                .polygons
+                .iter()
+                .map(|p| match &p.curve {
+                    Some(c) => Shape {
+                        tpe: p.tpe,
+                        points: c.vertices.clone(),
+                        smooth: Some((c.kind, c.step)),
+                        properties: p.properties.clone(),
+                    },
+                    None => Shape {
+                        tpe: p.tpe,
+                        points: p.points.clone(),
+                        smooth: None,
+                        properties: p.properties.clone(),
+                    },
+                })
+                .collect(),
+            start: level.start,
+            stars: level.stars.iter().map(|s| s.pos).collect(),
+        };
+        Ok(Editor {
+            path: path.to_string(),
+            json,
+            data,
+            undo: vec![],
+            redo: vec![],
+            view: level.start,
+            level,
+            tool: Tool::Wall,
+            snap: true,
+            zoom: 1.0,
+            mouse: Vec2::ZERO,
+            hover: None,
+            drawing: vec![],
+            drag: None,
+            message: String::new(),
+            unsaved: false,
+            leaving: false,
+        })
+    }
+
+    // the edited level in Tiled's JSON map format
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut json = self.json.clone();
+        let mut next_id = json["nextobjectid"].as_u64().unwrap_or(1);
+        let mut id = || {
+            next_id += 1;
+            next_id - 1
+        };
+
+        let mut polygon_objects = |tpe: PolygonType| -> Vec<serde_json::Value> {
+            self.data
+                .shapes
+                .iter()
+                .filter(|s| s.tpe == tpe)
+                .map(|s| {
+                    let mut properties = s.properties.clone();
+                    properties.remove("smooth");
+                    properties.remove("smooth_step");
+                    if let Some((kind, step)) = s.smooth {
+                        properties.set("smooth", Property::String(kind.name().to_string()));
+                        properties.set("smooth_step", Property::Float(step));
+                    }
+                    serde_json::json!({
+                        "id": id(),
+                        "name": "",
+                        "type": "",
+                        "x": 0,
+                        "y": 0,
+                        "width": 0,
+                        "height": 0,
+                        "rotation": 0,
+                        "visible": true,
+                        "polygon": s.points.iter().map(|p| json_point(*p)).collect::<Vec<_>>(),
+                        "properties": properties.to_json(),
+                    })
+                })
+                .collect()
+        };
+        let walls = polygon_objects(PolygonType::Wall);
+        let lava = polygon_objects(PolygonType::Lava);
+
+        let point_object = |name: &str, p: Vec2, id: u64| {
+            serde_json::json!({
+                "id": id,
+                "name": name,
+                "type": "",
+                "point": true,
+                "x": p.x,
+                "y": p.y,
+                "width": 0,
+                "height": 0,
+                "rotation": 0,
+                "visible": true,
+            })
+        };
+        let mut objects = vec![point_object("start", self.data.start, id())];
+        for p in self.data.stars.iter() {
+            objects.push(point_object("star", *p, id()));
+        }
+
+        if !json["layers"].is_array() {
+            json["layers"] = serde_json::json!([]);
+        }
+        let mut next_layer_id = json["nextlayerid"].as_u64().unwrap_or(1);
+        let layers = json["layers"].as_array_mut().unwrap();
+        for (name, new_objects) in [("walls", walls), ("lava", lava), ("objects", objects)] {
+            let mut new_objects = Some(new_objects);
+            replace_objects(layers, name, Vec2::ZERO, &mut new_objects);
+            if let Some(objects) = new_objects {
+                layers.push(serde_json::json!({
+                    "id": next_layer_id,
+                    "name": name,
+                    "type": "objectgroup",
+                    "draworder": "topdown",
+                    "opacity": 1,
+                    "visible": true,
+                    "x": 0,
+                    "y": 0,
+                    "objects": objects,
+                }));
+                next_layer_id += 1;
+            }
+        }
+        json["nextlayerid"] = next_layer_id.into();
+        json["nextobjectid"] = next_id.into();
+        json
+    }
+
+    pub fn build_level(&self) -> Result<Level, std::io::Error> {
+        Level::parse_file(&self.path, self.to_json().to_string().as_bytes())
+    }
+
+    // shown in the status line, e.g. when the edited level doesn't build
+    pub fn set_message(&mut self, message: String) {
+        self.message = message;
+    }
+
+    // Whether the editor can be closed, with unsaved changes it takes asking
+    // twice.
+    pub fn leave(&mut self) -> bool {
+        if !self.unsaved || self.leaving {
+            return true;
+        }
+        self.leaving = true;
+        self.message = "UNSAVED CHANGES, PRESS ESC AGAIN TO DISCARD".to_string();
+        false
+    }
+
+    pub fn save(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let text = serde_json::to_string_pretty(&self.to_json()).unwrap();
+            self.message = match std::fs::write(&self.path, text) {
+                Ok(()) => {
+                    self.unsaved = false;
+                    format!("SAVED {}", self.path)
+                }
+                Err(e) => format!("ERROR: {}", e),
+            };
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            self.message = "SAVING IS NOT SUPPORTED HERE".to_string();
+        }
+    }
+
+    fn camera(&self, canvas_size: Vec2) -> Camera2D {
+        let size = canvas_size / self.zoom;
+        let mut cam = Camera2D::from_display_rect(Rect::new(0.0, 0.0, size.x, size.y));
+        cam.zoom.y = cam.zoom.y.abs();
+        cam.target = self.view;
+        cam
+    }
+
+    fn snapped(&self, p: Vec2) -> Vec2 {
+        if self.snap {
+            (p / GRID).round() * GRID
+        } else {
+            p
+        }
+    }
+
+    fn push_undo(&mut self) {
+        self.undo.push(self.data.clone());
+        if self.undo.len() > UNDO_LIMIT {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+
+    fn handle_pos(&self, h: Handle) -> Vec2 {
+        match h {
+            Handle::Vertex(i, j) => self.data.shapes[i].points[j],
+            Handle::Star(i) => self.data.stars[i],
+            Handle::Start => self.data.start,
+        }
+    }
+
+    fn set_handle_pos(&mut self, h: Handle, p: Vec2) {
+        match h {
+            Handle::Vertex(i, j) => self.data.shapes[i].points[j] = p,
+            Handle::Star(i) => self.data.stars[i] = p,
+            Handle::Start => self.data.start = p,
+        }
+    }
+
+    fn find_handle(&self, p: Vec2) -> Option<Handle> {
+        let r = PICK_RADIUS / self.zoom;
+        let mut handles = vec![Handle::Start];
+        handles.extend((0..self.data.stars.len()).map(Handle::Star));
+        for (i, s) in self.data.shapes.iter().enumerate() {
+            handles.extend((0..s.points.len()).map(|j| Handle::Vertex(i, j)));
+        }
+        handles
+            .into_iter()
+            .map(|h| (h, self.handle_pos(h).distance(p)))
+            .filter(|(_, d)| *d < r)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(h, _)| h)
+    }
+
+    // the closest edge to the point, as shape and index of the following vertex
+    fn find_edge(&self, p: Vec2) -> Option<(usize, usize, Vec2)> {
+        let r = PICK_RADIUS / self.zoom;
+        let mut best: Option<(usize, usize, Vec2)> = None;
+        for (i, s) in self.data.shapes.iter().enumerate() {
+            for (j, a) in s.points.iter().enumerate() {
+                let b = s.points[(j + 1) % s.points.len()];
+                let q = project_on_segment(p, *a, b);
+                if q.distance(p) < r && best.is_none_or(|(_, _, bq)| q.distance(p) < bq.distance(p))
+                {
+                    best = Some((i, j + 1, q));
+                }
+            }
+        }
+        best
+    }
+
+    fn delete(&mut self, h: Handle) {
+        self.push_undo();
+        match h {
+            Handle::Vertex(i, j) => {
+                let points = &mut self.data.shapes[i].points;
+                points.remove(j);
+                if points.len() < 3 {
+                    self.data.shapes.remove(i);
+                }
+            }
+            Handle::Star(i) => {
+                self.data.stars.remove(i);
+            }
+            // there is always a start
+            Handle::Start => {}
+        }
+    }
+
+    // keeps the last preview that built if this one doesn't
+    fn rebuild(&mut self) {
+        match self.build_level() {
+            Ok(level) => self.level = level,
+            Err(e) => self.message = format!("ERROR: {}", e),
+        }
+        self.hover = None;
+    }
+
+    pub fn update(&mut self, canvas_size: Vec2) {
+        let dt = get_frame_time();
+        let ctrl = is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl);
+        let shift = is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift);
+        let mut changed = false;
+
+        // view
+        let wheel = mouse_wheel().1;
+        if wheel != 0.0 {
+            self.zoom = (self.zoom * if wheel > 0.0 { 1.1 } else { 1.0 / 1.1 }).clamp(0.2, 4.0);
+        }
+        let pan = vec2(
+            is_key_down(KeyCode::Right) as i32 as f32 - is_key_down(KeyCode::Left) as i32 as f32,
+            is_key_down(KeyCode::Down) as i32 as f32 - is_key_down(KeyCode::Up) as i32 as f32,
+        );
+        self.view += pan * PAN_SPEED * dt / self.zoom;
+
+        let cam = self.camera(canvas_size);
+        self.mouse = cam.screen_to_world(mouse_position().into());
+        let p = self.snapped(self.mouse);
+
+        // tools
+        for (key, tool) in [
+            (KeyCode::Key1, Tool::Wall),
+            (KeyCode::Key2, Tool::Lava),
+            (KeyCode::Key3, Tool::Star),
+            (KeyCode::Key4, Tool::Start),
+        ] {
+            if is_key_pressed(key) {
+                self.tool = tool;
+                self.drawing.clear();
+            }
+        }
+        if is_key_pressed(KeyCode::G) {
+            self.snap = !self.snap;
+        }
+
+        // undo and redo
+        if ctrl && is_key_pressed(KeyCode::Z) && !shift {
+            if let Some(data) = self.undo.pop() {
+                self.redo.push(std::mem::replace(&mut self.data, data));
+                changed = true;
+            }
+        }
+        if ctrl && (is_key_pressed(KeyCode::Y) || (shift && is_key_pressed(KeyCode::Z))) {
+            if let Some(data) = self.redo.pop() {
+                self.undo.push(std::mem::replace(&mut self.data, data));
+                changed = true;
+            }
+        }
+        if ctrl && is_key_pressed(KeyCode::S) {
+            self.save();
+        }
+
+        if self.drag.is_none() {
+            self.hover = self.find_handle(self.mouse);
+        }
+
+        // delete
+        if is_key_pressed(KeyCode::Delete) || is_key_pressed(KeyCode::X) {
+            if let Some(h) = self.hover {
+                self.delete(h);
+                changed = true;
+            }
+        }
+        if is_key_pressed(KeyCode::Backspace) {
+            if let Some(i) = self
+                .data
+                .shapes
+                .iter()
+                .rposition(|s| point_in_polygon(self.mouse, &s.points))
+            {
+                self.push_undo();
+                self.data.shapes.remove(i);
+                changed = true;
+            }
+        }
+
+        // smoothing of the shape under the cursor: none, Catmull-Rom, Bezier
+        if is_key_pressed(KeyCode::C) {
+            if let Some(i) = self
+                .data
+                .shapes
+                .iter()
+                .rposition(|s| point_in_polygon(self.mouse, &s.points))
+            {
+                self.push_undo();
+                let step = self.json["properties"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .find(|p| p["name"] == "smooth_step")
+                    .and_then(|p| p["value"].as_f64())
+                    .map_or(SMOOTH_STEP, |s| s as f32);
+                let shape = &mut self.data.shapes[i];
+                shape.smooth = match shape.smooth {
+                    None => Some((CurveKind::CatmullRom, step)),
+                    Some((CurveKind::CatmullRom, step)) => Some((CurveKind::Bezier, step)),
+                    Some((CurveKind::Bezier, _)) => None,
+                };
+                changed = true;
+            }
+        }
+
+        // mouse
+        if is_mouse_button_pressed(MouseButton::Right) {
+            if self.drawing.is_empty() {
+                self.drag = Some(Drag::Pan(mouse_position().into()));
+            } else {
+                self.drawing.clear();
+            }
+        }
+        if is_mouse_button_pressed(MouseButton::Left) {
+            if !self.drawing.is_empty() {
+                let close = self.drawing.len() >= 3
+                    && self.drawing[0].distance(self.mouse) < PICK_RADIUS / self.zoom;
+                if close {
+                    self.push_undo();
+                    let tpe = match self.tool {
+                        Tool::Lava => PolygonType::Lava,
+                        _ => PolygonType::Wall,
+                    };
+                    let points = std::mem::take(&mut self.drawing);
+                    self.data.shapes.push(Shape {
+                        tpe,
+                        points,
+                        smooth: None,
+                        properties: Default::default(),
+                    });
+                    changed = true;
+                } else {
+                    self.drawing.push(p);
+                }
+            } else if let Some(h) = self.hover {
+                self.push_undo();
+                self.drag = Some(Drag::Handle(h));
+            } else if let (true, Some((i, j, q))) = (shift, self.find_edge(self.mouse)) {
+                self.push_undo();
+                let q = self.snapped(q);
+                self.data.shapes[i].points.insert(j, q);
+                self.drag = Some(Drag::Handle(Handle::Vertex(i, j)));
+                changed = true;
+            } else {
+                match self.tool {
+                    Tool::Wall | Tool::Lava => self.drawing.push(p),
+                    Tool::Star => {
+                        self.push_undo();
+                        self.data.stars.push(p);
+                        changed = true;
+                    }
+                    Tool::Start => {
+                        self.push_undo();
+                        self.data.start = p;
+                        changed = true;
+                    }
+                }
+            }
+        }
+        match self.drag {
+            Some(Drag::Handle(h)) if self.handle_pos(h) != p => {
+                self.set_handle_pos(h, p);
+                changed = true;
+            }
+            Some(Drag::Pan(prev)) => {
+                let m: Vec2 = mouse_position().into();
+                self.view -= cam.screen_to_world(m) - cam.screen_to_world(prev);
+                self.drag = Some(Drag::Pan(m));
+            }
+            _ => {}
+        }
+        if !is_mouse_button_down(MouseButton::Left) && !is_mouse_button_down(MouseButton::Right) {
+            self.drag = None;
+        }
+
+        if changed {
+            self.message.clear();
+            self.unsaved = true;
+            self.leaving = false;
+            self.rebuild();
+        }
+    }
+
+    pub fn draw(
+        &self,
+        canvas_size: Vec2,
+        materials: &Materials,
+        textures: &Textures,
+        batch: &mut fx::Batch,
+    ) {
+        let cam = self.camera(canvas_size);
+        set_camera(&cam);
+
+        // grid
+        let r = 1.0 / self.zoom;
+        if self.snap {
+            let min = cam.screen_to_world(Vec2::ZERO);
+            let max = cam.screen_to_world(vec2(screen_width(), screen_height()));
+            let c = Color::new(1.0, 1.0, 1.0, 0.05);
+            let mut x = (min.x / GRID).floor() * GRID;
+            while x < max.x {
+                draw_line(x, min.y, x, max.y, r, c);
+                x += GRID;
+            }
+            let mut y = (min.y / GRID).floor() * GRID;
+            while y < max.y {
+                draw_line(min.x, y, max.x, y, r, c);
+                y += GRID;
+            }
+        }
+
+        self.level.draw_decorations(false, textures, &cam, batch);
+        self.level.draw(materials, cam.target, batch);
+        self.level.draw_decorations(true, textures, &cam, batch);
+
+        // outlines and vertices
+        let vertex = |batch: &mut fx::Batch, a: Vec2, c: Color| {
+            let h = 1.5 * r;
+            batch.polygon(
+                &[
+                    a + vec2(-h, -h),
+                    a + vec2(h, -h),
+                    a + vec2(h, h),
+                    a + vec2(-h, h),
+                ],
+                c,
+            );
+        };
+        for s in self.data.shapes.iter() {
+            let c = match s.tpe {
+                PolygonType::Wall => Color::from_rgba(60, 200, 140, 255),
+                PolygonType::Lava => Color::from_rgba(240, 100, 80, 255),
+            };
+            batch.outline(&s.points, true, r, c);
+            for a in s.points.iter() {
+                vertex(batch, *a, c);
+            }
+        }
+
+        let ring = |batch: &mut fx::Batch, center: Vec2, radius: f32, c: Color| {
+            let points: Vec<Vec2> = (0..20)
+                .map(|i| center + Vec2::from_angle(i as f32 / 20.0 * 2.0 * PI) * radius)
+                .collect();
+            batch.outline(&points, true, r, c);
+        };
+
+        // start
+        let s = self.data.start;
+        ring(batch, s, 6.0, WHITE);
+        batch.line(s, s + vec2(10.0, 0.0), r, WHITE);
+
+        // hovered handle
+        if let Some(h) = self.hover {
+            ring(batch, self.handle_pos(h), PICK_RADIUS * r, YELLOW);
+        }
+
+        // polygon in progress
+        if !self.drawing.is_empty() {
+            let c = Color::new(1.0, 1.0, 0.5, 1.0);
+            let mut points = self.drawing.clone();
+            points.push(self.snapped(self.mouse));
+            batch.outline(&points, false, r, c);
+            for a in self.drawing.iter() {
+                vertex(batch, *a, c);
+            }
+        }
+        batch.flush();
+
+        // status
+        let mut cam =
+            Camera2D::from_display_rect(Rect::new(0.0, 0.0, canvas_size.x, canvas_size.y));
+        cam.zoom.y = cam.zoom.y.abs();
+        set_camera(&cam);
+        let ui = Ui::new(&materials.font, canvas_size);
+        let tool = match self.tool {
+            Tool::Wall => "WALL",
+            Tool::Lava => "LAVA",
+            Tool::Star => "STAR",
+            Tool::Start => "START",
+        };
+        let snap = if self.snap { "SNAP ON" } else { "SNAP OFF" };
+        let status = format!("EDIT: {}  {}", tool, snap);
+        ui.text(
+            &status,
+            ui.at(TOP_LEFT, vec2(5.0, 12.0)),
+            Align::Left,
+            &ui.small,
+        );
+        if !self.message.is_empty() {
+            let at = ui.at(TOP_LEFT, vec2(5.0, 24.0));
+            ui.text_fit(
+                &self.message,
+                at,
+                Align::Left,
+                canvas_size.x - 10.0,
+                &ui.small,
+            );
+        }
+        ui.text_fit(
+            "1-4 TOOL  G SNAP  SHIFT+CLICK INSERT  X DELETE  C SMOOTH  P PLAY  CTRL+S SAVE  CTRL+Z UNDO",
+            ui.at(BOTTOM_LEFT, vec2(5.0, -5.0)),
+            Align::Left,
+            canvas_size.x - 10.0,
+            &ui.small,
+        );
+    }
+}
@@ -0,0 +1,343 @@
+// Imports Elasto Mania levels (.lev, "POT14").
+//
+// The file starts with a fixed header (version, link number, checksums, level
+// name, LGR, ground and sky textures), followed by counted lists of polygons,
+// objects and pictures. Counts are stored as doubles with a magic fraction
+// added. All numbers are little endian.
+//
+//     polygon: i32 grass, i32 vertex count, vertex count * (f64 x, f64 y)
+//     object:  f64 x, f64 y, i32 type, i32 gravity, i32 animation
+//
+// Elma polygons outline the air: the outermost polygon encloses the playable
+// space, polygons inside it are ground, polygons inside those are air again
+// and so on. Here ground is drawn as filled polygons, so every air polygon is
+// cut out of the ground around it, with a frame around the whole level as the
+// outermost ground.
+//
+// Apples become stars and the flower a star that can only be taken after all
+// apples, killers become lava circles. Grass polygons become green decorations
+// in front of the ground, pictures are skipped.
+
+use macroquad::prelude::*;
+
+use crate::bike::BikeParams;
+use crate::decoration::{self, Art, Decoration};
+use crate::level::{invalid_data, Level, Polygon, PolygonType, Star};
+
+const POLYGON_MAGIC: f64 = 0.4643643;
+const OBJECT_MAGIC: f64 = 0.4643643;
+const HEADER_SIZE: usize = 130;
+
+// wheel radius in Elma units, levels are scaled so that the wheels match
+const ELMA_WHEEL_R: f32 = 0.4;
+const KILLER_R: f32 = 0.4;
+const KILLER_SEGMENTS: usize = 12;
+// margin of the ground frame around the level, in Elma units
+const FRAME_MARGIN: f32 = 10.0;
+
+const OBJECT_FLOWER: i32 = 1;
+const OBJECT_APPLE: i32 = 2;
+const OBJECT_KILLER: i32 = 3;
+const OBJECT_START: i32 = 4;
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], std::io::Error> {
+        let b = self
+            .bytes
+            .get(self.pos..self.pos + N)
+            .ok_or_else(|| invalid_data("unexpected end of level file"))?;
+        self.pos += N;
+        Ok(b.try_into().unwrap())
+    }
+
+    fn i32(&mut self) -> Result<i32, std::io::Error> {
+        Ok(i32::from_le_bytes(self.take()?))
+    }
+
+    fn f64(&mut self) -> Result<f64, std::io::Error> {
+        Ok(f64::from_le_bytes(self.take()?))
+    }
+
+    fn count(&mut self, magic: f64) -> Result<usize, std::io::Error> {
+        let n = (self.f64()? - magic).round();
+        if !(0.0..=100000.0).contains(&n) {
+            return Err(invalid_data("invalid count"));
+        }
+        Ok(n as usize)
+    }
+}
+
+fn contains(polygon: &[Vec2], p: Vec2) -> bool {
+    let mut inside = false;
+    for (i, a) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % polygon.len()];
+        if (a.y > p.y) != (b.y > p.y) && p.x < a.x + (p.y - a.y) / (b.y - a.y) * (b.x - a.x) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+// vertices may lie on the edge of the surrounding polygon, so most of them decide
+fn inside(polygon: &[Vec2], other: &[Vec2]) -> bool {
+    other.iter().filter(|p| contains(polygon, **p)).count() * 2 >= other.len()
+}
+
+fn signed_area(polygon: &[Vec2]) -> f32 {
+    let mut s = 0.0;
+    for (i, p) in polygon.iter().enumerate() {
+        s += p.perp_dot(polygon[(i + 1) % polygon.len()]);
+    }
+    s * 0.5
+}
+
+fn segments_cross(a: Vec2, b: Vec2, c: Vec2, d: Vec2) -> bool {
+    let side = |p: Vec2, q: Vec2, r: Vec2| (q - p).perp_dot(r - p);
+    let (d1, d2) = (side(c, d, a), side(c, d, b));
+    let (d3, d4) = (side(a, b, c), side(a, b, d));
+    d1 * d2 < 0.0 && d3 * d4 < 0.0
+}
+
+// Merges holes into a polygon by connecting each one to the outline with a
+// zero width bridge, so that it can be triangulated as a single polygon.
+fn cut_holes(outline: Vec<Vec2>, mut holes: Vec<Vec<Vec2>>) -> Vec<Vec2> {
+    let mut outline = outline;
+    if signed_area(&outline) < 0.0 {
+        outline.reverse();
+    }
+    // rightmost holes first, so bridges don't cross holes still to come
+    holes.sort_by(|a, b| {
+        let max_x = |h: &Vec<Vec2>| h.iter().map(|p| p.x).fold(f32::MIN, f32::max);
+        max_x(b).total_cmp(&max_x(a))
+    });
+    for (k, mut hole) in holes.iter().cloned().enumerate() {
+        if signed_area(&hole) > 0.0 {
+            hole.reverse();
+        }
+        let (j, h) = hole
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.x.total_cmp(&b.1.x))
+            .map(|(j, p)| (j, *p))
+            .unwrap();
+        // the closest outline vertex that can be reached without crossing anything
+        let others = holes[k + 1..].iter();
+        let visible = |o: Vec2| {
+            let crosses = |poly: &[Vec2]| {
+                (0..poly.len()).any(|i| {
+                    let (c, d) = (poly[i], poly[(i + 1) % poly.len()]);
+                    segments_cross(h, o, c, d)
+                })
+            };
+            !crosses(&outline) && !crosses(&hole) && !others.clone().any(|p| crosses(p))
+        };
+        let best = outline
+            .iter()
+            .enumerate()
+            .filter(|(_, o)| visible(**o))
+            .min_by(|a, b| a.1.distance(h).total_cmp(&b.1.distance(h)))
+            .map(|(i, _)| i);
+        let Some(i) = best else {
+            continue;
+        };
+        let mut merged = outline[..=i].to_vec();
+        merged.extend(hole[j..].iter().chain(hole[..=j].iter()));
+        merged.extend(outline[i..].iter());
+        outline = merged;
+    }
+    outline
+}
+
+pub fn parse(bytes: &[u8]) -> Result<Level, std::io::Error> {
+    if !bytes.starts_with(b"POT14") {
+        return Err(invalid_data("not an Elasto Mania level"));
+    }
+    let mut r = Reader {
+        bytes,
+        pos: HEADER_SIZE,
+    };
+    let scale = BikeParams::default().wheel_r / ELMA_WHEEL_R;
+
+    let mut air = vec![];
+    let mut grass = vec![];
+    for _ in 0..r.count(POLYGON_MAGIC)? {
+        let is_grass = r.i32()? != 0;
+        let n = r.i32()?;
+        if !(0..=100000).contains(&n) {
+            return Err(invalid_data("invalid vertex count"));
+        }
+        let mut points = vec![];
+        for _ in 0..n {
+            let x = r.f64()? as f32;
+            let y = r.f64()? as f32;
+            points.push(vec2(x, y) * scale);
+        }
+        if points.len() < 3 {
+            continue;
+        }
+        if is_grass {
+            grass.push((points, decoration::GRASS_COLOR));
+        } else {
+            air.push(points);
+        }
+    }
+
+    let mut level = Level::default();
+    for _ in 0..r.count(OBJECT_MAGIC)? {
+        let pos = vec2(r.f64()? as f32, r.f64()? as f32) * scale;
+        let tpe = r.i32()?;
+        let _gravity = r.i32()?;
+        let _animation = r.i32()?;
+        match tpe {
+            OBJECT_FLOWER => level.stars.push(Star::flower(pos)),
+            OBJECT_APPLE => level.stars.push(Star::new(pos)),
+            OBJECT_KILLER => level.polygons.push(Polygon {
+                tpe: PolygonType::Lava,
+                points: (0..KILLER_SEGMENTS)
+                    .map(|i| {
+                        let a = i as f32 / KILLER_SEGMENTS as f32 * std::f32::consts::TAU;
+                        pos + Vec2::from_angle(a) * KILLER_R * scale
+                    })
+                    .collect(),
+                curve: None,
+                properties: Default::default(),
+            }),
+            // the start object is the rider, the bike is created above the start point
+            OBJECT_START => level.start = pos + vec2(0.0, 20.0),
+            _ => {}
+        }
+    }
+    if air.is_empty() {
+        return Err(invalid_data("level has no polygons"));
+    }
+
+    // nesting depth of every polygon, even depths are air
+    let depth: Vec<usize> = air
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            air.iter()
+                .enumerate()
+                .filter(|(j, q)| *j != i && inside(q, p))
+                .count()
+        })
+        .collect();
+
+    // ground frame around everything
+    let (min, max) = air.iter().flatten().fold(
+        (Vec2::splat(f32::MAX), Vec2::splat(f32::MIN)),
+        |(min, max), p| (min.min(*p), max.max(*p)),
+    );
+    let m = Vec2::splat(FRAME_MARGIN * scale);
+    let (min, max) = (min - m, max + m);
+    let frame = vec![min, vec2(max.x, min.y), max, vec2(min.x, max.y)];
+
+    let children = |ground: Option<usize>| -> Vec<Vec<Vec2>> {
+        air.iter()
+            .enumerate()
+            .filter(|(i, p)| match ground {
+                None => depth[*i] == 0,
+                Some(g) => depth[*i] == depth[g] + 1 && inside(&air[g], p),
+            })
+            .map(|(_, p)| p.clone())
+            .collect()
+    };
+    let mut ground = vec![cut_holes(frame, children(None))];
+    for (i, p) in air.iter().enumerate() {
+        if depth[i] % 2 == 1 {
+            ground.push(cut_holes(p.clone(), children(Some(i))));
+        }
+    }
+    level
+        .polygons
+        .extend(ground.into_iter().map(|points| Polygon {
+            tpe: PolygonType::Wall,
+            points,
+            curve: None,
+            properties: Default::default(),
+        }));
+
+    if !grass.is_empty() {
+        level.decorations.push(Decoration {
+            art: Art::Polygons(decoration::polygons_batch(grass)),
+            parallax: Vec2::ONE,
+            front: true,
+            tint: WHITE,
+        });
+    }
+
+    level.build();
+    Ok(level)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level_bytes() -> Vec<u8> {
+        let mut b = b"POT14".to_vec();
+        b.resize(HEADER_SIZE, 0);
+        // one air polygon
+        b.extend((1.0 + POLYGON_MAGIC).to_le_bytes());
+        b.extend(0i32.to_le_bytes());
+        b.extend(4i32.to_le_bytes());
+        for (x, y) in [(0.0f64, 0.0f64), (20.0, 0.0), (20.0, 10.0), (0.0, 10.0)] {
+            b.extend(x.to_le_bytes());
+            b.extend(y.to_le_bytes());
+        }
+        // flower, apple and start
+        b.extend((3.0 + OBJECT_MAGIC).to_le_bytes());
+        for (x, y, tpe) in [
+            (15.0f64, 5.0f64, OBJECT_FLOWER),
+            (10.0, 5.0, OBJECT_APPLE),
+            (5.0, 5.0, OBJECT_START),
+        ] {
+            b.extend(x.to_le_bytes());
+            b.extend(y.to_le_bytes());
+            b.extend(tpe.to_le_bytes());
+            b.extend(0i32.to_le_bytes());
+            b.extend(0i32.to_le_bytes());
+        }
+        b
+    }
+
+    #[test]
+    fn parses_level() {
+        let level = parse(&level_bytes()).unwrap();
+        let scale = BikeParams::default().wheel_r / ELMA_WHEEL_R;
+        assert_eq!(level.stars.len(), 2);
+        assert_eq!(level.stars_left, 2);
+        assert!(level.stars[0].flower);
+        assert!(!level.stars[1].flower);
+        assert_eq!(level.stars[1].pos, vec2(10.0, 5.0) * scale);
+        assert_eq!(level.start, vec2(5.0, 5.0) * scale + vec2(0.0, 20.0));
+        assert!(!level.polygons.is_empty());
+    }
+
+    #[test]
+    fn flower_is_taken_last() {
+        let mut level = parse(&level_bytes()).unwrap();
+        let scale = BikeParams::default().wheel_r / ELMA_WHEEL_R;
+        level.pickup_stars(vec2(15.0, 5.0) * scale, 0.1);
+        assert_eq!(level.stars_left, 2);
+        level.pickup_stars(vec2(10.0, 5.0) * scale, 0.1);
+        assert_eq!(level.stars_left, 1);
+        level.pickup_stars(vec2(15.0, 5.0) * scale, 0.1);
+        assert_eq!(level.stars_left, 0);
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        let mut b = level_bytes();
+        assert!(parse(&b[..b.len() - 4]).is_err());
+        b[HEADER_SIZE..HEADER_SIZE + 8].copy_from_slice(&(-3.0f64).to_le_bytes());
+        assert!(parse(&b).is_err());
+        b[..5].copy_from_slice(b"POT12");
+        assert!(parse(&b).is_err());
+    }
+}
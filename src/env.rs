@@ -0,0 +1,161 @@
+// A gym-style environment for training and benchmarking driving agents.
+// It runs the same physics as the game but needs no window, so it can step
+// through levels as fast as the CPU allows.
+//
+// Observation layout, all values are f32:
+//
+//     index  size  content
+//     0      2     frame position relative to the level start / OBS_SCALE
+//     2      2     sin and cos of the frame angle
+//     4      2     frame velocity / OBS_SCALE
+//     6      1     frame angular velocity
+//     7      6     rear wheel: position relative to the frame / OBS_SCALE,
+//                  velocity / OBS_SCALE, angular velocity / 10, contact (0 or 1)
+//     13     6     front wheel, same as the rear wheel
+//     19     1     direction (1 right, -1 left)
+//     20     16    distance to walls along RAYS rays / RAY_LENGTH (1 = nothing hit),
+//                  starting straight right and going clockwise, relative to the world
+//     36     16    distance to lava, same rays as the walls
+//     52     6     vectors to the NEAREST_STARS nearest stars / OBS_SCALE,
+//                  closest first, zero when there are fewer stars left
+//     58     1     fraction of stars left
+//
+// The layout only ever grows at the end, so existing agents keep working.
+
+use macroquad::prelude::*;
+use std::f32::consts::PI;
+
+use crate::bike::{Bike, BikeParams, Direction, Input, PHYSICS_DT};
+use crate::level::{Level, PolygonType};
+
+pub const RAYS: usize = 16;
+pub const RAY_LENGTH: f32 = 200.0;
+pub const NEAREST_STARS: usize = 3;
+pub const OBS_SCALE: f32 = 100.0;
+pub const OBS_SIZE: usize = 20 + 2 * RAYS + 2 * NEAREST_STARS + 1;
+
+pub const STAR_REWARD: f32 = 1.0;
+pub const FINISH_REWARD: f32 = 1.0;
+pub const DEATH_REWARD: f32 = -1.0;
+// per second
+pub const TIME_REWARD: f32 = -0.1;
+
+pub type Observation = [f32; OBS_SIZE];
+
+pub struct Step {
+    pub observation: Observation,
+    pub reward: f32,
+    pub done: bool,
+}
+
+pub struct Env {
+    pub level: Level,
+    pub bike: Bike,
+    pub params: BikeParams,
+    // physics ticks per call to step
+    pub ticks_per_step: u32,
+    // episodes end after this many seconds
+    pub time_limit: f32,
+    pub time: f32,
+}
+
+impl Env {
+    // params are the bike profile, level overrides are applied on top
+    pub fn new(level: Level, params: BikeParams) -> Self {
+        let params = level.bike_params(params);
+        let bike = Bike::new(level.start, params);
+        Env {
+            level,
+            bike,
+            params,
+            ticks_per_step: 50,
+            time_limit: 60.0,
+            time: 0.0,
+        }
+    }
+
+    pub fn reset(&mut self) -> Observation {
+        self.level.restart();
+        self.bike = Bike::new(self.level.start, self.params);
+        self.time = 0.0;
+        self.observation()
+    }
+
+    pub fn finished(&self) -> bool {
+        self.level.stars_left == 0
+    }
+
+    pub fn step(&mut self, action: &Input) -> Step {
+        let mut reward = 0.0;
+        for _ in 0..self.ticks_per_step {
+            let stars_left = self.level.stars_left;
+            self.bike.update(PHYSICS_DT, &mut self.level, action);
+            self.time += PHYSICS_DT;
+            reward += (stars_left - self.level.stars_left) as f32 * STAR_REWARD;
+            reward += TIME_REWARD * PHYSICS_DT;
+            if self.finished() {
+                reward += FINISH_REWARD;
+                break;
+            }
+            if !self.bike.alive {
+                reward += DEATH_REWARD;
+                break;
+            }
+        }
+        Step {
+            observation: self.observation(),
+            reward,
+            done: self.finished() || !self.bike.alive || self.time >= self.time_limit,
+        }
+    }
+
+    pub fn observation(&self) -> Observation {
+        let mut obs = [0.0; OBS_SIZE];
+        let frame = &self.bike.frame;
+        let contact = self.bike.wheel_contact();
+
+        let mut values = vec![
+            (frame.pos.x - self.level.start.x) / OBS_SCALE,
+            (frame.pos.y - self.level.start.y) / OBS_SCALE,
+            frame.ang.sin(),
+            frame.ang.cos(),
+            frame.vel.x / OBS_SCALE,
+            frame.vel.y / OBS_SCALE,
+            frame.ang_vel,
+        ];
+        for (wheel, c) in self.bike.wheels.iter().zip(contact) {
+            let p = (wheel.pos - frame.pos) / OBS_SCALE;
+            let v = wheel.vel / OBS_SCALE;
+            values.extend([p.x, p.y, v.x, v.y, wheel.ang_vel / 10.0, c as u8 as f32]);
+        }
+        values.push(match self.bike.dir {
+            Direction::Right => 1.0,
+            Direction::Left => -1.0,
+        });
+
+        for tpe in [PolygonType::Wall, PolygonType::Lava] {
+            for i in 0..RAYS {
+                let dir = Vec2::from_angle(i as f32 * 2.0 * PI / RAYS as f32);
+                let d = self.level.raycast(frame.pos, dir, RAY_LENGTH, tpe);
+                values.push(d / RAY_LENGTH);
+            }
+        }
+
+        let mut stars: Vec<Vec2> = self
+            .level
+            .takeable_stars()
+            .map(|s| (s.pos - frame.pos) / OBS_SCALE)
+            .collect();
+        stars.sort_by(|a, b| a.length_squared().total_cmp(&b.length_squared()));
+        stars.resize(NEAREST_STARS, Vec2::ZERO);
+        for s in stars.iter() {
+            values.extend([s.x, s.y]);
+        }
+
+        let total = self.level.stars.len().max(1);
+        values.push(self.level.stars_left as f32 / total as f32);
+
+        obs.copy_from_slice(&values);
+        obs
+    }
+}
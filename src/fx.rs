@@ -3,6 +3,13 @@ use std::f32::consts::PI;
 
 type Vert = macroquad::models::Vertex;
 
+// macroquad cuts draw calls off at 10000 vertices and 5000 indices
+const MAX_VERTICES: usize = 8192;
+const MAX_INDICES: usize = 4800;
+
+// outline corners sharper than this become bevels, in multiples of the half width
+const MITER_LIMIT: f32 = 4.0;
+
 pub fn vert(p: Vec2, color: Color) -> Vert {
     Vert {
         position: p.extend(0.0),
@@ -12,146 +19,315 @@ pub fn vert(p: Vec2, color: Color) -> Vert {
     }
 }
 
-// triangle fan
-// TODO: handle all concave polygons
-pub fn draw_polygon(points: &[Vec2], color: Color) {
-    let mut iter = points.iter().map(|p| vert(*p, color));
-    let mut vertices = vec![
-        iter.next().unwrap(),
-        iter.next().unwrap(), //
-    ];
-    let mut indices = vec![];
-    for (i, v) in iter.enumerate() {
-        let i = i as u16;
-        vertices.push(v);
-        indices.extend_from_slice(&[0, i + 1, i + 2]);
-    }
-    draw_mesh(&Mesh {
-        vertices,
-        indices,
+fn empty_mesh() -> Mesh {
+    Mesh {
+        vertices: vec![],
+        indices: vec![],
         texture: None,
-    });
+    }
 }
 
-pub fn draw_wheel(pos: Vec2, ang: f32, radius: f32, color: Color) {
-    const N: u16 = 7;
-
-    const W: f32 = 2.0;
-    let radius = radius - W * 0.5;
-    let mut vertices = vec![];
-    let mut indices = vec![];
-
-    for i in 0..=N {
-        let f = i as f32 / N as f32 * 2.0 * PI;
-        let n = Vec2::from_angle(ang + f);
-        let f = (i as f32 - 0.5) / N as f32 * 2.0 * PI;
-        let b1 = Vec2::from_angle(ang + f) * W;
-        let f = (i as f32 + 0.5) / N as f32 * 2.0 * PI;
-        let b2 = Vec2::from_angle(ang + f) * W;
-
-        vertices.push(vert(pos + n * radius + b1, color));
-        vertices.push(vert(pos + n * radius, color));
-        vertices.push(vert(pos + n * radius + b2, color));
-
-        if i > 0 {
-            indices.extend_from_slice(&[
-                i * 3 - 3,
-                i * 3 - 2,
-                i * 3 - 1,
-                //
-                i * 3 - 2,
-                i * 3 - 1,
-                i * 3,
-                //
-                i * 3 - 2,
-                i * 3,
-                i * 3 + 1,
-            ])
-        }
-    }
-
-    draw_mesh(&Mesh {
-        vertices,
-        indices,
-        texture: None,
-    });
+// Shapes collected into as few meshes as macroquad can draw, one draw call
+// each. Shapes are drawn in the order they were added.
+pub struct Batch {
+    meshes: Vec<Mesh>,
 }
 
-pub fn draw_limb(p: Vec2, q: Vec2, w: f32, v: f32, c: Color) {
-    const N: u16 = 8;
-    let w = w * 0.5;
-    let v = v * 0.5;
-
-    let pq = q - p;
-    let wv = v - w;
-
-    let alpha = pq.x.atan2(pq.y);
-    let beta = wv.atan2((pq.length_squared() - wv * wv).sqrt());
-    let a1 = PI - alpha + beta;
-    let a2 = 2.0 * PI - alpha - beta;
-
-    let mut vertices = vec![
-        vert(p, c), //
-        vert(q, c),
-    ];
-    let mut indices = vec![];
-
-    for i in 0..=N {
-        let m = i as f32 / N as f32;
-        let a = a1 * (1.0 - m) + a2 * m;
-        let pp = p + Vec2::from_angle(a) * w;
-        draw_line(p.x, p.y, pp.x, pp.y, 1.0, c);
-        vertices.push(vert(pp, c));
-        if i > 0 {
-            indices.extend_from_slice(&[
-                0, //
-                i + 1,
-                i + 2,
-            ]);
-        }
-    }
-
-    let a1 = a1 + 2.0 * PI;
-    for i in 0..=N {
-        let m = i as f32 / N as f32;
-        let a = a2 * (1.0 - m) + a1 * m;
-        let qq = q + Vec2::from_angle(a) * v;
-        draw_line(q.x, q.y, qq.x, qq.y, 1.0, c);
-        vertices.push(vert(qq, c));
-        if i > 0 {
-            indices.extend_from_slice(&[
-                1, //
-                N + i + 2,
-                N + i + 3,
-            ]);
-        }
-    }
-
-    indices.extend_from_slice(&[
-        0,
-        1,
-        2, //
-        1,
-        2,
-        N + N + 3,
-        1,
-        0,
-        N + 3,
-        0,
-        N + 3,
-        N + 2,
-    ]);
-
-    draw_mesh(&Mesh {
-        vertices,
-        indices,
-        texture: None,
-    });
+impl Default for Batch {
+    fn default() -> Self {
+        Batch {
+            meshes: vec![empty_mesh()],
+        }
+    }
+}
+
+impl Batch {
+    pub fn clear(&mut self) {
+        self.meshes.truncate(1);
+        self.meshes[0].vertices.clear();
+        self.meshes[0].indices.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.meshes[0].indices.is_empty()
+    }
+
+    pub fn draw(&self) {
+        for mesh in self.meshes.iter().filter(|m| !m.indices.is_empty()) {
+            draw_mesh(mesh);
+        }
+    }
+
+    // draws what was added so far and starts over, needed before drawing
+    // with a texture, a material or another camera
+    pub fn flush(&mut self) {
+        self.draw();
+        self.clear();
+    }
+
+    pub fn triangles(&self) -> impl Iterator<Item = [Vec2; 3]> + '_ {
+        self.meshes.iter().flat_map(|m| {
+            m.indices.chunks(3).map(|t| {
+                let p = |i: u16| m.vertices[i as usize].position.truncate();
+                [p(t[0]), p(t[1]), p(t[2])]
+            })
+        })
+    }
+
+    // the same shapes with their colors multiplied by tint
+    pub fn tinted(&self, tint: Color) -> Batch {
+        let tint: [f32; 4] = tint.into();
+        let meshes = self
+            .meshes
+            .iter()
+            .map(|m| {
+                let mut vertices = m.vertices.clone();
+                for v in vertices.iter_mut() {
+                    for (c, t) in v.color.iter_mut().zip(tint) {
+                        *c = (*c as f32 * t) as u8;
+                    }
+                }
+                Mesh {
+                    vertices,
+                    indices: m.indices.clone(),
+                    texture: None,
+                }
+            })
+            .collect();
+        Batch { meshes }
+    }
+
+    // the mesh to add a shape of this size to, and the index of its first vertex
+    fn room(&mut self, vertices: usize, indices: usize) -> (&mut Mesh, u16) {
+        let last = self.meshes.last().unwrap();
+        if last.vertices.len() + vertices > MAX_VERTICES
+            || last.indices.len() + indices > MAX_INDICES
+        {
+            self.meshes.push(empty_mesh());
+        }
+        let mesh = self.meshes.last_mut().unwrap();
+        let n = mesh.vertices.len() as u16;
+        (mesh, n)
+    }
+
+    // Adds triangles, indices count from the first of the vertices.
+    // Shapes too big for one draw call are split into single triangles.
+    pub fn push(&mut self, vertices: &[Vert], indices: &[u32]) {
+        if vertices.len() > MAX_VERTICES || indices.len() > MAX_INDICES {
+            for t in indices.chunks_exact(3) {
+                let triangle = [t[0], t[1], t[2]].map(|i| vertices[i as usize]);
+                self.push(&triangle, &[0, 1, 2]);
+            }
+            return;
+        }
+        let (mesh, n) = self.room(vertices.len(), indices.len());
+        mesh.vertices.extend_from_slice(vertices);
+        // fits, a mesh has less than u16::MAX vertices
+        mesh.indices.extend(indices.iter().map(|i| *i as u16 + n));
+    }
+
+    // any simple polygon, in either direction
+    pub fn polygon(&mut self, points: &[Vec2], color: Color) {
+        if points.len() < 3 {
+            return;
+        }
+        let vertices: Vec<Vert> = points.iter().map(|p| vert(*p, color)).collect();
+        self.push(&vertices, &triangulate_polygon(points));
+    }
+
+    pub fn line(&mut self, p: Vec2, q: Vec2, width: f32, color: Color) {
+        let n = (q - p).normalize_or_zero().perp() * width * 0.5;
+        let vertices = [p + n, q + n, q - n, p - n].map(|p| vert(p, color));
+        self.push(&vertices, &[0, 1, 2, 0, 2, 3]);
+    }
+
+    // Lines along the points, closed back to the first one or not, with
+    // mitered corners.
+    pub fn outline(&mut self, points: &[Vec2], closed: bool, width: f32, color: Color) {
+        let n = points.len();
+        if n < 2 {
+            return;
+        }
+        let h = width * 0.5;
+        let edge_normal = |i: usize| {
+            (points[(i + 1) % n] - points[i % n])
+                .normalize_or_zero()
+                .perp()
+        };
+        let mut vertices = Vec::with_capacity(n * 2);
+        for (i, p) in points.iter().enumerate() {
+            let before = if closed || i > 0 {
+                Some(edge_normal(i + n - 1))
+            } else {
+                None
+            };
+            let after = if closed || i < n - 1 {
+                Some(edge_normal(i))
+            } else {
+                None
+            };
+            let offset = match (before, after) {
+                (Some(a), Some(b)) => {
+                    let m = (a + b).normalize_or_zero();
+                    let cos = m.dot(b);
+                    if cos * MITER_LIMIT > 1.0 {
+                        m * h / cos
+                    } else {
+                        b * h
+                    }
+                }
+                (a, b) => a.or(b).unwrap() * h,
+            };
+            vertices.push(vert(*p + offset, color));
+            vertices.push(vert(*p - offset, color));
+        }
+        let edges = if closed { n } else { n - 1 };
+        let mut indices = Vec::with_capacity(edges * 6);
+        for i in 0..edges {
+            let a = i as u32 * 2;
+            let b = ((i + 1) % n) as u32 * 2;
+            indices.extend_from_slice(&[a, b, b + 1, a, b + 1, a + 1]);
+        }
+        self.push(&vertices, &indices);
+    }
+
+    // a regular polygon, rotated by ang
+    pub fn circle(&mut self, center: Vec2, radius: f32, sides: u32, ang: f32, color: Color) {
+        let mut vertices = vec![vert(center, color)];
+        let mut indices = vec![];
+        for i in 0..sides {
+            let a = ang + i as f32 / sides as f32 * 2.0 * PI;
+            vertices.push(vert(center + Vec2::from_angle(a) * radius, color));
+            indices.extend_from_slice(&[0, i + 1, (i + 1) % sides + 1]);
+        }
+        self.push(&vertices, &indices);
+    }
+
+    pub fn wheel(&mut self, pos: Vec2, ang: f32, radius: f32, color: Color) {
+        const N: u32 = 7;
+
+        const W: f32 = 2.0;
+        let radius = radius - W * 0.5;
+        let mut vertices = vec![];
+        let mut indices = vec![];
+
+        for i in 0..=N {
+            let f = i as f32 / N as f32 * 2.0 * PI;
+            let n = Vec2::from_angle(ang + f);
+            let f = (i as f32 - 0.5) / N as f32 * 2.0 * PI;
+            let b1 = Vec2::from_angle(ang + f) * W;
+            let f = (i as f32 + 0.5) / N as f32 * 2.0 * PI;
+            let b2 = Vec2::from_angle(ang + f) * W;
+
+            vertices.push(vert(pos + n * radius + b1, color));
+            vertices.push(vert(pos + n * radius, color));
+            vertices.push(vert(pos + n * radius + b2, color));
+
+            if i > 0 {
+                indices.extend_from_slice(&[
+                    i * 3 - 3,
+                    i * 3 - 2,
+                    i * 3 - 1,
+                    //
+                    i * 3 - 2,
+                    i * 3 - 1,
+                    i * 3,
+                    //
+                    i * 3 - 2,
+                    i * 3,
+                    i * 3 + 1,
+                ])
+            }
+        }
+
+        self.push(&vertices, &indices);
+    }
+
+    pub fn limb(&mut self, p: Vec2, q: Vec2, w: f32, v: f32, c: Color) {
+        const N: u32 = 8;
+        let w = w * 0.5;
+        let v = v * 0.5;
+
+        let pq = q - p;
+        let wv = v - w;
+
+        let alpha = pq.x.atan2(pq.y);
+        let beta = wv.atan2((pq.length_squared() - wv * wv).sqrt());
+        let a1 = PI - alpha + beta;
+        let a2 = 2.0 * PI - alpha - beta;
+
+        let mut vertices = vec![
+            vert(p, c), //
+            vert(q, c),
+        ];
+        let mut indices = vec![];
+
+        for i in 0..=N {
+            let m = i as f32 / N as f32;
+            let a = a1 * (1.0 - m) + a2 * m;
+            let pp = p + Vec2::from_angle(a) * w;
+            self.line(p, pp, 1.0, c);
+            vertices.push(vert(pp, c));
+            if i > 0 {
+                indices.extend_from_slice(&[
+                    0, //
+                    i + 1,
+                    i + 2,
+                ]);
+            }
+        }
+
+        let a1 = a1 + 2.0 * PI;
+        for i in 0..=N {
+            let m = i as f32 / N as f32;
+            let a = a2 * (1.0 - m) + a1 * m;
+            let qq = q + Vec2::from_angle(a) * v;
+            self.line(q, qq, 1.0, c);
+            vertices.push(vert(qq, c));
+            if i > 0 {
+                indices.extend_from_slice(&[
+                    1, //
+                    N + i + 2,
+                    N + i + 3,
+                ]);
+            }
+        }
+
+        indices.extend_from_slice(&[
+            0,
+            1,
+            2, //
+            1,
+            2,
+            N + N + 3,
+            1,
+            0,
+            N + 3,
+            0,
+            N + 3,
+            N + 2,
+        ]);
+
+        self.push(&vertices, &indices);
+    }
+}
+
+// single shapes, for when there's nothing to batch them with
+pub fn draw_polygon(points: &[Vec2], color: Color) {
+    let mut batch = Batch::default();
+    batch.polygon(points, color);
+    batch.draw();
+}
+
+pub fn draw_outline(points: &[Vec2], closed: bool, width: f32, color: Color) {
+    let mut batch = Batch::default();
+    batch.outline(points, closed, width, color);
+    batch.draw();
 }
 
 fn orientation(p: Vec2, q: Vec2, r: Vec2) -> bool {
     let val = (q - p).perp_dot(r - q);
-    return val <= 0.0;
+    val <= 0.0
 }
 fn is_inside_triangle(p: Vec2, a: Vec2, b: Vec2, c: Vec2) -> bool {
     orientation(p, a, b) && orientation(p, b, c) && orientation(p, c, a)
@@ -176,6 +352,10 @@ fn is_ear(polygon: &[Vec2], todo: &[usize], i: usize) -> bool {
             continue;
         }
         let q = polygon[j];
+        // duplicates of the corners, as left by bridged holes, don't block the ear
+        if q == prev_p || q == ear_p || q == next_p {
+            continue;
+        }
         if is_inside_triangle(q, prev_p, ear_p, next_p) {
             return false;
         }
@@ -183,29 +363,44 @@ fn is_ear(polygon: &[Vec2], todo: &[usize], i: usize) -> bool {
     true
 }
 
-pub fn triangulate_polygon(polygon: &[Vec2]) -> Vec<u16> {
+// Ear clipping, for simple polygons in either direction.
+pub fn triangulate_polygon(polygon: &[Vec2]) -> Vec<u32> {
     if polygon.len() < 3 {
         return Vec::new();
     }
 
-    let mut indices: Vec<u16> = Vec::new();
-    let mut todo: Vec<usize> = (0..polygon.len()).collect();
+    // ears are found going one way around, the other way is walked backwards
+    let area: f32 = (0..polygon.len())
+        .map(|i| polygon[i].perp_dot(polygon[(i + 1) % polygon.len()]))
+        .sum();
+    let mut todo: Vec<usize> = if area > 0.0 {
+        (0..polygon.len()).rev().collect()
+    } else {
+        (0..polygon.len()).collect()
+    };
+    let mut indices: Vec<u32> = Vec::new();
 
     while todo.len() > 2 {
         let num_remaining = todo.len();
+        // self-intersecting polygons may run out of ears
+        let mut found = false;
         for i in 0..num_remaining {
             if is_ear(polygon, &todo, i) {
+                found = true;
                 let n = todo.len();
                 let ear_i = todo[i];
                 let prev_i = todo[if i == 0 { n - 1 } else { i - 1 }];
                 let next_i = todo[if i == n - 1 { 0 } else { i + 1 }];
                 todo.remove(i);
-                indices.push(prev_i as u16);
-                indices.push(ear_i as u16);
-                indices.push(next_i as u16);
+                indices.push(prev_i as u32);
+                indices.push(ear_i as u32);
+                indices.push(next_i as u32);
                 break;
             }
         }
+        if !found {
+            break;
+        }
     }
     indices
 }
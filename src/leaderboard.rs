@@ -0,0 +1,351 @@
+// Shared leaderboard over HTTP/JSON. Levels and bike profiles are identified
+// by hashes (8 hex digits) so that only runs on identical levels and bikes
+// are compared, times are written like the in-game clock.
+//
+//     POST /submit
+//     {"player": "name", "level": "<level hash>", "profile": "<profile hash>",
+//      "time": "00:05:27", "replay": "<replay file contents>"}
+//     -> 200 {"rank": 3}
+//     -> 400 {"error": "..."}
+//
+//     GET /top?level=<level hash>&profile=<profile hash>&count=10
+//     -> 200 {"times": [{"player": "name", "time": "00:05:27"}, ...]}
+//
+// The server plays every submitted replay back on its own copy of the level
+// and only accepts it if the run collects all stars in the claimed time.
+// Each player keeps their best time per level and profile. Runs over 10
+// minutes, requests over 1 MB and connections over 10 seconds are refused.
+//
+// The game submits runs when BIKE_LEADERBOARD is set to the server address
+// (e.g. 127.0.0.1:8000), BIKE_PLAYER sets the player name.
+
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use crate::bike::{BikeParams, PHYSICS_DT};
+use crate::level::Level;
+use crate::replay::Replay;
+use crate::LevelTime;
+
+pub const TOP_COUNT: usize = 5;
+// top times are fetched again after this long
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_NAME_LEN: usize = 16;
+// a whole request or response, sent and received
+const TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_HEADER: usize = 8 * 1024;
+const MAX_BODY: usize = 1024 * 1024;
+// longest run the server checks, in hundredths of a second
+const MAX_TIME: u32 = 10 * 60 * 100;
+
+#[derive(Clone)]
+pub struct Entry {
+    pub player: String,
+    pub time: LevelTime,
+}
+
+pub fn hex(hash: u32) -> String {
+    format!("{:08x}", hash)
+}
+
+pub fn parse_hex(s: &str) -> Option<u32> {
+    u32::from_str_radix(s, 16).ok()
+}
+
+// keeps names printable with the game font
+pub fn clean_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-')
+        .take(MAX_NAME_LEN)
+        .collect();
+    if name.is_empty() {
+        "anonymous".to_string()
+    } else {
+        name
+    }
+}
+
+// Plays the replay back and returns the time it takes to collect all stars.
+// The replay may not be longer than the claimed time, which bounds the work of
+// checking runs from untrusted clients.
+pub fn verify(
+    replay: &Replay,
+    level: &mut Level,
+    profile: BikeParams,
+    claimed: LevelTime,
+) -> Result<LevelTime, String> {
+    if replay.profile != profile.hash() {
+        return Err("replay was made with another bike profile".to_string());
+    }
+    if claimed == LevelTime::invalid() || claimed.0 > MAX_TIME {
+        return Err("invalid time".to_string());
+    }
+    // times are cut to hundredths, the run ends before the next one
+    let max_ticks = ((claimed.0 + 1) as f64 * 0.01 / PHYSICS_DT as f64) as u64 + 1;
+    let ticks = replay
+        .inputs
+        .iter()
+        .try_fold(0u64, |t, (n, _)| t.checked_add(*n as u64))
+        .filter(|&t| t <= max_ticks)
+        .ok_or_else(|| "replay is longer than the claimed time".to_string())?;
+    let params = level.bike_params(profile);
+    let ticks = replay
+        .run(level, params, ticks)
+        .ok_or_else(|| "replay does not collect all stars".to_string())?;
+    Ok(LevelTime::new(ticks as f32 * PHYSICS_DT))
+}
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    pub body: String,
+}
+
+// A message of at most MAX_HEADER + MAX_BODY bytes, read until the deadline.
+// Err has the status to answer with.
+fn read_message(
+    stream: &mut TcpStream,
+    deadline: Instant,
+) -> Result<(String, String), (u16, String)> {
+    let mut data = vec![];
+    let mut buf = [0; 4096];
+    let read = |stream: &mut TcpStream, buf: &mut [u8]| {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err((408, "timed out".to_string()));
+        }
+        stream
+            .set_read_timeout(Some(left))
+            .and_then(|_| stream.read(buf))
+            .map_err(|e| match e.kind() {
+                ErrorKind::WouldBlock | ErrorKind::TimedOut => (408, "timed out".to_string()),
+                _ => (400, e.to_string()),
+            })
+    };
+    let header_end = loop {
+        let n = read(stream, &mut buf)?;
+        if n == 0 {
+            return Err((400, "connection closed".to_string()));
+        }
+        data.extend_from_slice(&buf[..n]);
+        if let Some(i) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+            break i;
+        }
+        if data.len() > MAX_HEADER {
+            return Err((413, "header too large".to_string()));
+        }
+    };
+    let head = String::from_utf8_lossy(&data[..header_end]).to_string();
+    let length = head
+        .lines()
+        .filter_map(|l| l.split_once(':'))
+        .find(|(k, _)| k.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, v)| v.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    if length > MAX_BODY {
+        return Err((413, "body too large".to_string()));
+    }
+    let mut body = data[header_end + 4..].to_vec();
+    while body.len() < length {
+        let n = read(stream, &mut buf)?;
+        if n == 0 {
+            break;
+        }
+        body.extend_from_slice(&buf[..n]);
+    }
+    body.truncate(length);
+    Ok((head, String::from_utf8_lossy(&body).to_string()))
+}
+
+pub fn read_request(stream: &mut TcpStream) -> Result<Request, (u16, String)> {
+    let (head, body) = read_message(stream, Instant::now() + TIMEOUT)?;
+    let mut words = head.lines().next().unwrap_or_default().split_whitespace();
+    let method = words.next().unwrap_or_default().to_string();
+    let target = words.next().unwrap_or_default();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query = query
+        .split('&')
+        .filter_map(|kv| kv.split_once('='))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    Ok(Request {
+        method,
+        path: path.to_string(),
+        query,
+        body,
+    })
+}
+
+pub fn write_response(stream: &mut impl Write, status: u16, json: &serde_json::Value) {
+    let body = json.to_string();
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        408 => "Request Timeout",
+        413 => "Payload Too Large",
+        _ => "Not Found",
+    };
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        body
+    );
+}
+
+// a blocking request, the body is sent as JSON when given
+pub fn request(
+    addr: &str,
+    method: &str,
+    path: &str,
+    body: Option<&serde_json::Value>,
+) -> Result<serde_json::Value, String> {
+    let deadline = Instant::now() + TIMEOUT;
+    let mut stream = TcpStream::connect(addr).map_err(|e| e.to_string())?;
+    let body = body.map(|b| b.to_string()).unwrap_or_default();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        addr,
+        body.len(),
+        body
+    )
+    .map_err(|e| e.to_string())?;
+    let (_, body) = read_message(&mut stream, deadline).map_err(|(_, e)| e)?;
+    let json: serde_json::Value = serde_json::from_str(&body).map_err(|e| e.to_string())?;
+    match json["error"].as_str() {
+        Some(e) => Err(e.to_string()),
+        None => Ok(json),
+    }
+}
+
+pub fn parse_top(json: &serde_json::Value) -> Vec<Entry> {
+    json["times"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|e| {
+            Some(Entry {
+                player: e["player"].as_str()?.to_string(),
+                time: e["time"].as_str()?.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+fn top_path(level: u32, profile: u32) -> String {
+    format!(
+        "/top?level={}&profile={}&count={}",
+        hex(level),
+        hex(profile),
+        TOP_COUNT
+    )
+}
+
+enum Reply {
+    Top(u32, u32, Vec<Entry>),
+    Submitted(Result<u64, String>),
+}
+
+// Talks to the server from background threads so the game never waits.
+pub struct Client {
+    addr: String,
+    player: String,
+    sender: std::sync::mpsc::Sender<Reply>,
+    receiver: std::sync::mpsc::Receiver<Reply>,
+    // top times by level and profile hash
+    pub top: HashMap<(u32, u32), Vec<Entry>>,
+    fetched: HashMap<(u32, u32), Instant>,
+    pub status: Option<String>,
+}
+
+impl Client {
+    // configured through environment variables, native builds only
+    pub fn from_env() -> Option<Client> {
+        if cfg!(target_arch = "wasm32") {
+            return None;
+        }
+        let addr = std::env::var("BIKE_LEADERBOARD").ok()?;
+        let player = clean_name(&std::env::var("BIKE_PLAYER").unwrap_or_default());
+        let (sender, receiver) = std::sync::mpsc::channel();
+        Some(Client {
+            addr,
+            player,
+            sender,
+            receiver,
+            top: HashMap::new(),
+            fetched: HashMap::new(),
+            status: None,
+        })
+    }
+
+    // fetches the top times unless they are fresh enough
+    pub fn want_top(&mut self, level: u32, profile: u32) {
+        let key = (level, profile);
+        if self
+            .fetched
+            .get(&key)
+            .is_none_or(|t| t.elapsed() > REFRESH_INTERVAL)
+        {
+            self.fetched.insert(key, Instant::now());
+            self.fetch_top(level, profile);
+        }
+    }
+
+    fn fetch_top(&self, level: u32, profile: u32) {
+        let addr = self.addr.clone();
+        let sender = self.sender.clone();
+        let path = top_path(level, profile);
+        std::thread::spawn(move || {
+            if let Ok(json) = request(&addr, "GET", &path, None) {
+                let _ = sender.send(Reply::Top(level, profile, parse_top(&json)));
+            }
+        });
+    }
+
+    pub fn submit(&self, replay: &Replay, level: u32, time: LevelTime) {
+        let addr = self.addr.clone();
+        let sender = self.sender.clone();
+        let body = serde_json::json!({
+            "player": self.player,
+            "level": hex(level),
+            "profile": hex(replay.profile),
+            "time": time.to_string(),
+            "replay": replay.to_string(),
+        });
+        let profile = replay.profile;
+        std::thread::spawn(move || {
+            let result = request(&addr, "POST", "/submit", Some(&body))
+                .and_then(|json| json["rank"].as_u64().ok_or("invalid reply".to_string()));
+            let _ = sender.send(Reply::Submitted(result));
+            if let Ok(json) = request(&addr, "GET", &top_path(level, profile), None) {
+                let _ = sender.send(Reply::Top(level, profile, parse_top(&json)));
+            }
+        });
+    }
+
+    // pick up finished requests
+    pub fn poll(&mut self) {
+        while let Ok(reply) = self.receiver.try_recv() {
+            match reply {
+                Reply::Top(level, profile, entries) => {
+                    self.top.insert((level, profile), entries);
+                }
+                Reply::Submitted(Ok(rank)) => {
+                    self.status = Some(format!("LEADERBOARD RANK {}", rank))
+                }
+                Reply::Submitted(Err(e)) => {
+                    self.status = Some(format!("LEADERBOARD: {}", e.to_uppercase()))
+                }
+            }
+        }
+    }
+}
@@ -1,12 +1,16 @@
 use macroquad::prelude::*;
+use std::borrow::Cow;
 use std::f32::consts::PI;
 
+use crate::bike::BikeParams;
+use crate::decoration::{self, Decoration};
 use crate::fx;
-use crate::materials::Materials;
+use crate::materials::{Materials, Theme};
+use crate::properties::Properties;
 
 const STAR_R: f32 = 10.0;
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Copy)]
 pub struct CollisionInfo {
     pub normal: Vec2,
     pub dist: f32,
@@ -19,29 +23,104 @@ pub enum CollisionResult {
     Wall(CollisionInfo),
 }
 
-enum PolygonType {
+#[derive(PartialEq, Clone, Copy)]
+pub enum PolygonType {
     Wall,
     Lava,
 }
 
-struct Polygon {
-    tpe: PolygonType,
-    points: Vec<Vec2>,
+#[derive(PartialEq, Clone, Copy)]
+pub enum CurveKind {
+    // passes through the vertices
+    CatmullRom,
+    // quadratic Bezier curves between edge midpoints, rounds off the corners
+    Bezier,
+}
+
+// Outline of a smoothed polygon, set through the "smooth" and "smooth_step"
+// properties of Tiled polygon objects.
+#[derive(Clone)]
+pub struct Curve {
+    pub kind: CurveKind,
+    // longest edge of the tessellated outline
+    pub step: f32,
+    pub vertices: Vec<Vec2>,
+}
+
+pub struct Polygon {
+    pub tpe: PolygonType,
+    // the edges used for collision and drawing
+    pub points: Vec<Vec2>,
+    // the curve the points were tessellated from
+    pub curve: Option<Curve>,
+    // custom properties of the Tiled object
+    pub properties: Properties,
 }
 
 pub struct Star {
     alive: bool,
-    pos: Vec2,
+    pub pos: Vec2,
+    // like the flower of Elma: can only be taken after all other stars and
+    // takes the remaining flowers with it
+    pub flower: bool,
+}
+
+impl Star {
+    pub fn new(pos: Vec2) -> Self {
+        Star {
+            alive: true,
+            pos,
+            flower: false,
+        }
+    }
+
+    pub fn flower(pos: Vec2) -> Self {
+        Star {
+            flower: true,
+            ..Star::new(pos)
+        }
+    }
+}
+
+// Custom properties of a Tiled map. "title" and "designer" are known to the
+// game, everything else can be looked up in the property bags of the map and
+// of the layers.
+#[derive(Default)]
+pub struct LevelMeta {
+    pub title: Option<String>,
+    pub designer: Option<String>,
+    pub properties: Properties,
+    // by layer name, in the order of the file
+    pub layers: Vec<(String, Properties)>,
+}
+
+impl LevelMeta {
+    pub fn layer(&self, name: &str) -> Option<&Properties> {
+        self.layers.iter().find(|(n, _)| n == name).map(|(_, p)| p)
+    }
 }
 
 pub struct Level {
     pub start: Vec2,
     pub stars: Vec<Star>,
     pub stars_left: usize,
-    polygons: Vec<Polygon>,
+    // bronze, silver, gold and author times in seconds
+    pub par_times: Option<[f32; 4]>,
+    // hash of the level file, identifies the level on the leaderboard
+    pub hash: u32,
+    // "bike.<field>" and "gravity" map properties, applied on top of the chosen bike profile
+    pub bike_overrides: Vec<(String, f32)>,
+    pub meta: LevelMeta,
+    // colors and shaders set by the level, the rest comes from the level pack
+    pub theme: Theme,
+    pub polygons: Vec<Polygon>,
+    pub decorations: Vec<Decoration>,
+    // where layers with parallax line up with the level, from the map's parallax origin
+    pub parallax_origin: Vec2,
     time: f32,
-    wall_mesh: Mesh,
-    lava_mesh: Mesh,
+    wall_mesh: fx::Batch,
+    lava_mesh: fx::Batch,
+    edges: EdgeGrid,
 }
 
 impl Default for Level {
@@ -51,18 +130,112 @@ impl Default for Level {
             start: vec2(0.0, 0.0),
             stars: vec![],
             stars_left: 0,
+            par_times: None,
+            hash: 0,
+            bike_overrides: vec![],
+            meta: Default::default(),
+            theme: Default::default(),
+            decorations: vec![],
+            parallax_origin: Vec2::ZERO,
             time: 0.0,
-            wall_mesh: Mesh {
-                vertices: vec![],
-                indices: vec![],
-                texture: None,
-            },
-            lava_mesh: Mesh {
-                vertices: vec![],
-                indices: vec![],
-                texture: None,
-            },
+            wall_mesh: Default::default(),
+            lava_mesh: Default::default(),
+            edges: Default::default(),
+        }
+    }
+}
+
+// smallest cell of the edge grid, about the size of the bike
+const GRID_CELL: f32 = 64.0;
+// large levels get bigger cells instead of more of them
+const MAX_GRID_CELLS: f32 = 65536.0;
+
+// The edges of all polygons sorted into square cells, so collision queries
+// only look at the edges around them. Smoothed polygons have thousands of
+// edges, checking all of them every physics tick is too slow.
+#[derive(Default)]
+struct EdgeGrid {
+    origin: Vec2,
+    cell: f32,
+    size: IVec2,
+    // (polygon, edge) pairs in level order
+    cells: Vec<Vec<(u32, u32)>>,
+}
+
+impl EdgeGrid {
+    fn new(polygons: &[Polygon]) -> Self {
+        let points = || polygons.iter().flat_map(|p| p.points.iter().copied());
+        let Some(first) = points().next() else {
+            return Default::default();
+        };
+        let (min, max) = points().fold((first, first), |(a, b), p| (a.min(p), b.max(p)));
+        let extent = max - min;
+        let cell = GRID_CELL.max((extent.x * extent.y / MAX_GRID_CELLS).sqrt());
+        let size = (extent / cell).floor().as_ivec2() + 1;
+        let mut grid = EdgeGrid {
+            origin: min,
+            cell,
+            size,
+            cells: vec![vec![]; (size.x * size.y) as usize],
+        };
+
+        // walk the rows an edge crosses and add it to the cells of its span in
+        // each row, with some slack so rounding never loses an edge
+        const SLACK: f32 = 0.5;
+        for (i, poly) in polygons.iter().enumerate() {
+            for (j, &p) in poly.points.iter().enumerate() {
+                let q = poly.points[(j + 1) % poly.points.len()];
+                let (a, b) = if p.y <= q.y { (p, q) } else { (q, p) };
+                let x_at = |y: f32| {
+                    if b.y - a.y < 1e-6 {
+                        return (a.x.min(b.x), a.x.max(b.x));
+                    }
+                    let y = y.clamp(a.y, b.y);
+                    let x = a.x + (b.x - a.x) * (y - a.y) / (b.y - a.y);
+                    (x, x)
+                };
+                let y0 = grid.cell_of(a - SLACK).y;
+                let y1 = grid.cell_of(b + SLACK).y;
+                for y in y0..=y1 {
+                    let top = grid.origin.y + y as f32 * cell;
+                    let (l0, r0) = x_at(top - SLACK);
+                    let (l1, r1) = x_at(top + cell + SLACK);
+                    let x0 = grid.cell_of(vec2(l0.min(l1) - SLACK, top)).x;
+                    let x1 = grid.cell_of(vec2(r0.max(r1) + SLACK, top)).x;
+                    for x in x0..=x1 {
+                        grid.cells[(y * size.x + x) as usize].push((i as u32, j as u32));
+                    }
+                }
+            }
+        }
+        grid
+    }
+
+    fn cell_of(&self, p: Vec2) -> IVec2 {
+        ((p - self.origin) / self.cell)
+            .floor()
+            .as_ivec2()
+            .clamp(IVec2::ZERO, self.size - 1)
+    }
+
+    // edges that may touch the box, in level order and without duplicates
+    fn edges(&self, min: Vec2, max: Vec2) -> Cow<'_, [(u32, u32)]> {
+        if self.cells.is_empty() {
+            return Cow::Borrowed(&[]);
+        }
+        let (a, b) = (self.cell_of(min), self.cell_of(max));
+        if a == b {
+            return Cow::Borrowed(&self.cells[(a.y * self.size.x + a.x) as usize]);
         }
+        let mut edges = vec![];
+        for y in a.y..=b.y {
+            for x in a.x..=b.x {
+                edges.extend_from_slice(&self.cells[(y * self.size.x + x) as usize]);
+            }
+        }
+        edges.sort_unstable();
+        edges.dedup();
+        Cow::Owned(edges)
     }
 }
 
@@ -104,7 +277,169 @@ fn circle_line_collision(m: Vec2, r: f32, p: Vec2, q: Vec2) -> Option<CollisionI
     None
 }
 
-fn fix_points(points: &mut Vec<Vec2>) {
+// default for the "smooth_step" property, and the smallest one allowed,
+// finer steps only make more points to triangulate
+pub const SMOOTH_STEP: f32 = 4.0;
+pub const MIN_SMOOTH_STEP: f32 = 0.5;
+
+fn read_smooth_step(properties: &Properties, default: f32) -> Result<f32, std::io::Error> {
+    let step = properties.f32("smooth_step").unwrap_or(default);
+    if step < MIN_SMOOTH_STEP {
+        return Err(invalid_data(&format!(
+            "smooth_step must be at least {}",
+            MIN_SMOOTH_STEP
+        )));
+    }
+    Ok(step)
+}
+
+impl CurveKind {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "catmull-rom" => Some(CurveKind::CatmullRom),
+            "bezier" => Some(CurveKind::Bezier),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CurveKind::CatmullRom => "catmull-rom",
+            CurveKind::Bezier => "bezier",
+        }
+    }
+}
+
+impl Curve {
+    pub fn tessellate(&self) -> Vec<Vec2> {
+        let v = &self.vertices;
+        let n = v.len();
+        let at = |i: usize| v[i % n];
+        let mut points = vec![];
+        for i in 0..n {
+            let (p0, p1, p2, p3) = (at(i + n - 1), at(i), at(i + 1), at(i + 2));
+            match self.kind {
+                CurveKind::CatmullRom => {
+                    let steps = (p1.distance(p2) / self.step).ceil().max(1.0) as usize;
+                    for k in 0..steps {
+                        let t = k as f32 / steps as f32;
+                        let (t2, t3) = (t * t, t * t * t);
+                        points.push(
+                            0.5 * (2.0 * p1
+                                + (p2 - p0) * t
+                                + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+                                + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3),
+                        );
+                    }
+                }
+                CurveKind::Bezier => {
+                    let a = p0.lerp(p1, 0.5);
+                    let b = p1.lerp(p2, 0.5);
+                    let len = a.distance(p1) + p1.distance(b);
+                    let steps = (len / self.step).ceil().max(1.0) as usize;
+                    for k in 0..steps {
+                        let t = k as f32 / steps as f32;
+                        points.push(a.lerp(p1, t).lerp(p1.lerp(b, t), t));
+                    }
+                }
+            }
+        }
+        points
+    }
+}
+
+// default for the "thickness" property of polylines
+const POLYLINE_THICKNESS: f32 = 4.0;
+
+// Tiled ellipse objects are given by their bounding box
+fn ellipse_points(size: Vec2, step: f32) -> Vec<Vec2> {
+    let r = size / 2.0;
+    let perimeter = PI * (r.x + r.y);
+    let n = (perimeter / step).ceil().max(8.0) as usize;
+    (0..n)
+        .map(|i| {
+            let a = i as f32 / n as f32 * 2.0 * PI;
+            r + vec2(a.cos(), a.sin()) * r
+        })
+        .collect()
+}
+
+// outline of a polyline widened to the given thickness, with mitered joins
+fn polyline_strip(points: &[Vec2], thickness: f32) -> Vec<Vec2> {
+    let mut points = points.to_vec();
+    points.dedup();
+    if points.len() < 2 {
+        return vec![];
+    }
+    let half = thickness / 2.0;
+    let normal = |i: usize| (points[i + 1] - points[i]).normalize().perp();
+    let mut left = vec![];
+    let mut right = vec![];
+    for i in 0..points.len() {
+        let n = match (i.checked_sub(1), i + 1 < points.len()) {
+            (Some(prev), true) => {
+                let (a, b) = (normal(prev), normal(i));
+                let n = (a + b).normalize_or(a);
+                // limit the miter at sharp turns
+                n * (half / n.dot(a).max(0.25))
+            }
+            (Some(prev), false) => normal(prev) * half,
+            (None, _) => normal(i) * half,
+        };
+        left.push(points[i] + n);
+        right.push(points[i] - n);
+    }
+    left.extend(right.into_iter().rev());
+    left
+}
+
+// The outline of a Tiled object relative to its position, before rotation.
+// Point and text objects have none.
+fn object_outline(
+    o: &serde_json::Value,
+    properties: &Properties,
+    step: f32,
+) -> Result<Option<Vec<Vec2>>, std::io::Error> {
+    let size = vec2(
+        o["width"].as_f64().unwrap_or(0.0) as f32,
+        o["height"].as_f64().unwrap_or(0.0) as f32,
+    );
+    let points = |name| -> Result<Vec<Vec2>, std::io::Error> {
+        array(o, name)?.iter().map(vec_from_json).collect()
+    };
+    Ok(if o["point"] == true || o["text"].is_object() {
+        None
+    } else if o["ellipse"] == true {
+        Some(ellipse_points(size, step))
+    } else if o["polygon"].is_array() {
+        Some(points("polygon")?)
+    } else if o["polyline"].is_array() {
+        let thickness = properties.f32("thickness").unwrap_or(POLYLINE_THICKNESS);
+        Some(polyline_strip(&points("polyline")?, thickness))
+    } else {
+        Some(vec![Vec2::ZERO, vec2(size.x, 0.0), size, vec2(0.0, size.y)])
+    })
+}
+
+// The outline of a Tiled object in level coordinates
+fn object_points(
+    o: &serde_json::Value,
+    offset: Vec2,
+    properties: &Properties,
+    step: f32,
+) -> Result<Option<Vec<Vec2>>, std::io::Error> {
+    let pos = offset + vec_from_json(o)?;
+    let Some(points) = object_outline(o, properties, step)? else {
+        return Ok(None);
+    };
+    // Tiled rotates clockwise around the object's position
+    let rotation = Vec2::from_angle((o["rotation"].as_f64().unwrap_or(0.0) as f32).to_radians());
+    Ok(Some(
+        points.iter().map(|p| pos + rotation.rotate(*p)).collect(),
+    ))
+}
+
+pub(crate) fn fix_points(points: &mut [Vec2]) {
     let mut s = 0.0;
     for i in 0..points.len() {
         let p = &points[i];
@@ -116,50 +451,216 @@ fn fix_points(points: &mut Vec<Vec2>) {
     }
 }
 
-fn vec_from_json(json: &serde_json::Value) -> Vec2 {
-    Vec2 {
-        x: json["x"].as_f64().unwrap() as f32,
-        y: json["y"].as_f64().unwrap() as f32,
-    }
+pub(crate) fn invalid_data(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+fn vec_from_json(json: &serde_json::Value) -> Result<Vec2, std::io::Error> {
+    let coord = |c: &str| {
+        json[c]
+            .as_f64()
+            .ok_or_else(|| invalid_data("invalid coordinate"))
+    };
+    Ok(Vec2 {
+        x: coord("x")? as f32,
+        y: coord("y")? as f32,
+    })
+}
+
+fn array<'a>(
+    json: &'a serde_json::Value,
+    name: &str,
+) -> Result<&'a Vec<serde_json::Value>, std::io::Error> {
+    json[name]
+        .as_array()
+        .ok_or_else(|| invalid_data(&format!("missing {}", name)))
 }
 
 impl Level {
     pub async fn load(path: &str) -> Result<Level, std::io::Error> {
+        let bytes = macroquad::file::load_file(path)
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::NotFound, e.to_string()))?;
+        Level::parse_file(path, &bytes)
+    }
+
+    // picks the format by file extension, Tiled maps are the default
+    pub fn parse_file(path: &str, bytes: &[u8]) -> Result<Level, std::io::Error> {
+        let mut level = if path.ends_with(".lev") {
+            crate::elma::parse(bytes)?
+        } else {
+            let string =
+                std::str::from_utf8(bytes).map_err(|_| invalid_data("level is not UTF-8"))?;
+            if path.ends_with(".svg") {
+                crate::svg::parse(string)?
+            } else {
+                Level::parse(string)?
+            }
+        };
+        level.hash = crate::fnv1a(bytes);
+        // images and shaders are relative to the level file
+        let dir = path.rsplit_once('/').map_or("", |(dir, _)| dir);
+        for d in level.decorations.iter_mut() {
+            d.resolve_paths(dir);
+        }
+        level.theme.resolve_paths(dir);
+        Ok(level)
+    }
+
+    pub fn parse(string: &str) -> Result<Level, std::io::Error> {
         let mut level = Level::default();
-        let string = macroquad::file::load_string(path).await.unwrap();
-        let json: serde_json::Value = serde_json::from_str(&string)?;
+        let json: serde_json::Value = serde_json::from_str(string)?;
+        level.hash = crate::fnv1a(string.as_bytes());
+        let properties = Properties::parse(&json)?;
+
+        let par_time = |name| properties.f32(name);
+        if let (Some(b), Some(s), Some(g), Some(a)) = (
+            par_time("bronze"),
+            par_time("silver"),
+            par_time("gold"),
+            par_time("author"),
+        ) {
+            level.par_times = Some([b, s, g, a]);
+        }
+
+        for (name, _) in properties.iter() {
+            let field = match name {
+                "gravity" => "gravity",
+                _ => match name.strip_prefix("bike.") {
+                    Some(field) => field,
+                    None => continue,
+                },
+            };
+            let value = properties
+                .f32(name)
+                .ok_or_else(|| invalid_data(&format!("{} is not a number", name)))?;
+            if !BikeParams::default().set(field, value) {
+                return Err(invalid_data(&format!("unknown bike parameter {}", field)));
+            }
+            level.bike_overrides.push((field.to_string(), value));
+        }
+
+        let smooth_step = read_smooth_step(&properties, SMOOTH_STEP)?;
+        level.theme = Theme::parse(&properties)?;
+        level.meta = LevelMeta {
+            title: properties.str("title").map(|s| s.to_string()),
+            designer: properties.str("designer").map(|s| s.to_string()),
+            properties,
+            layers: vec![],
+        };
+        level.parallax_origin = vec2(
+            json["parallaxoriginx"].as_f64().unwrap_or(0.0) as f32,
+            json["parallaxoriginy"].as_f64().unwrap_or(0.0) as f32,
+        );
+        level.parse_layers(&json, array(&json, "layers")?, Vec2::ZERO, smooth_step)?;
 
-        for layer in json["layers"].as_array().unwrap() {
-            let name = layer["name"].as_str().unwrap();
+        level.build();
+        Ok(level)
+    }
+
+    // group layers are walked recursively, their offsets add up
+    fn parse_layers(
+        &mut self,
+        map: &serde_json::Value,
+        layers: &[serde_json::Value],
+        offset: Vec2,
+        smooth_step: f32,
+    ) -> Result<(), std::io::Error> {
+        for layer in layers {
+            let name = layer["name"].as_str().unwrap_or_default();
+            let offset = offset
+                + vec2(
+                    layer["offsetx"].as_f64().unwrap_or(0.0) as f32,
+                    layer["offsety"].as_f64().unwrap_or(0.0) as f32,
+                );
+            // decorations after the walls are drawn in front of them
+            let front = self.meta.layers.iter().any(|(n, _)| n == "walls");
+            let visible = layer["visible"] != false;
+            self.meta
+                .layers
+                .push((name.to_string(), Properties::parse(layer)?));
+            if layer["type"] == "group" {
+                self.parse_layers(map, array(layer, "layers")?, offset, smooth_step)?;
+                continue;
+            }
+            if layer["type"] == "imagelayer" || layer["type"] == "tilelayer" {
+                let art = if layer["type"] == "imagelayer" {
+                    decoration::image_layer(layer, offset)
+                } else {
+                    Some(decoration::tile_layer(map, layer, offset)?)
+                };
+                if let Some(art) = art.filter(|_| visible) {
+                    self.decorations
+                        .push(Decoration::from_layer(layer, art, front));
+                }
+                continue;
+            }
             match name {
                 "walls" | "lava" => {
-                    for o in layer["objects"].as_array().unwrap() {
+                    for o in array(layer, "objects")? {
+                        let properties = Properties::parse(o)?;
                         let mut poly = Polygon {
                             tpe: match name {
                                 "walls" => PolygonType::Wall,
                                 _ => PolygonType::Lava,
                             },
                             points: vec![],
+                            curve: None,
+                            properties: Default::default(),
                         };
-                        let pos = vec_from_json(o);
-                        for p in o["polygon"].as_array().unwrap() {
-                            poly.points.push(pos + vec_from_json(p));
+                        let step = read_smooth_step(&properties, smooth_step)?;
+                        let Some(points) = object_points(o, offset, &properties, step)? else {
+                            continue;
+                        };
+                        poly.points = points;
+                        if poly.points.len() < 3 {
+                            continue;
+                        }
+                        if let Some(smooth) = properties.str("smooth") {
+                            let kind = CurveKind::from_name(smooth).ok_or_else(|| {
+                                invalid_data(&format!("unknown smoothing {}", smooth))
+                            })?;
+                            let curve = Curve {
+                                kind,
+                                step,
+                                vertices: std::mem::take(&mut poly.points),
+                            };
+                            poly.points = curve.tessellate();
+                            poly.curve = Some(curve);
                         }
-                        fix_points(&mut poly.points);
-                        level.polygons.push(poly);
+                        poly.properties = properties;
+                        self.polygons.push(poly);
                     }
                 }
+                "decoration" if visible => {
+                    let layer_color = Properties::parse(layer)?.color("color");
+                    let mut polygons = vec![];
+                    for o in array(layer, "objects")? {
+                        let properties = Properties::parse(o)?;
+                        let step = read_smooth_step(&properties, smooth_step)?;
+                        let Some(points) = object_points(o, offset, &properties, step)? else {
+                            continue;
+                        };
+                        let color = properties
+                            .color("color")
+                            .or(layer_color)
+                            .unwrap_or(decoration::GRASS_COLOR);
+                        polygons.push((points, color));
+                    }
+                    let art = decoration::Art::Polygons(decoration::polygons_batch(polygons));
+                    self.decorations
+                        .push(Decoration::from_layer(layer, art, front));
+                }
                 "objects" => {
-                    for o in layer["objects"].as_array().unwrap() {
-                        let name = o["name"].as_str().unwrap();
-                        let pos = vec_from_json(o);
+                    for o in array(layer, "objects")? {
+                        let name = o["name"].as_str().unwrap_or_default();
+                        let pos = offset + vec_from_json(o)?;
                         match name {
                             "start" => {
-                                level.start = pos;
+                                self.start = pos;
                             }
                             "star" => {
-                                level.stars.push(Star { alive: true, pos });
-                                level.stars_left += 1;
+                                self.stars.push(Star::new(pos));
                             }
                             _ => {}
                         }
@@ -168,40 +669,59 @@ impl Level {
                 _ => {}
             }
         }
+        Ok(())
+    }
+
+    // shared by all level formats once polygons, stars and start are in place
+    pub(crate) fn build(&mut self) {
+        for poly in self.polygons.iter_mut() {
+            fix_points(&mut poly.points);
+        }
+        self.stars_left = self.stars.iter().filter(|s| s.alive).count();
 
         // generate mesh
         let color = Color::new(1.0, 1.0, 1.0, 1.0);
-        for poly in level.polygons.iter() {
+        for poly in self.polygons.iter() {
             let mesh = match poly.tpe {
-                PolygonType::Wall => &mut level.wall_mesh,
-                PolygonType::Lava => &mut level.lava_mesh,
+                PolygonType::Wall => &mut self.wall_mesh,
+                PolygonType::Lava => &mut self.lava_mesh,
             };
-
-            let n = mesh.vertices.len();
-            mesh.vertices
-                .extend(poly.points.iter().map(|p| fx::vert(*p, color)));
-            let indices = fx::triangulate_polygon(&poly.points);
-            mesh.indices.extend(indices.iter().map(|i| *i + n as u16));
+            mesh.polygon(&poly.points, color);
         }
+        self.edges = EdgeGrid::new(&self.polygons);
+    }
 
-        Ok(level)
+    fn edge(&self, (i, j): (u32, u32)) -> (&Polygon, Vec2, Vec2) {
+        let poly = &self.polygons[i as usize];
+        let j = j as usize;
+        (
+            poly,
+            poly.points[j],
+            poly.points[(j + 1) % poly.points.len()],
+        )
+    }
+
+    pub fn bike_params(&self, profile: BikeParams) -> BikeParams {
+        let mut params = profile;
+        for (field, value) in self.bike_overrides.iter() {
+            params.set(field, *value);
+        }
+        params
     }
 
     // pub fn circle_collision(&self, pos: Vec2, r: f32) -> Option<CollisionInfo> {
     pub fn circle_collision(&self, pos: Vec2, r: f32) -> CollisionResult {
         let mut colli: Option<CollisionInfo> = None;
-        for poly in self.polygons.iter() {
-            for (i, p) in poly.points.iter().enumerate() {
-                let q = poly.points[(i + 1) % poly.points.len()];
-                if let Some(ci) = circle_line_collision(pos, r, *p, q) {
-                    if let PolygonType::Lava = poly.tpe {
-                        return CollisionResult::Lava;
-                    }
-                    colli = Some(match colli {
-                        Some(cc) if cc.dist > ci.dist => cc,
-                        _ => ci,
-                    })
+        for &edge in self.edges.edges(pos - r, pos + r).iter() {
+            let (poly, p, q) = self.edge(edge);
+            if let Some(ci) = circle_line_collision(pos, r, p, q) {
+                if let PolygonType::Lava = poly.tpe {
+                    return CollisionResult::Lava;
                 }
+                colli = Some(match colli {
+                    Some(cc) if cc.dist > ci.dist => cc,
+                    _ => ci,
+                })
             }
         }
         match colli {
@@ -210,11 +730,76 @@ impl Level {
         }
     }
 
+    // distance along a ray to the closest edge of the given polygon type
+    pub fn raycast(&self, origin: Vec2, dir: Vec2, max_dist: f32, tpe: PolygonType) -> f32 {
+        let mut dist = max_dist;
+        let end = origin + dir * max_dist;
+        for &edge in self.edges.edges(origin.min(end), origin.max(end)).iter() {
+            let (poly, p, q) = self.edge(edge);
+            if poly.tpe != tpe {
+                continue;
+            }
+            let e = q - p;
+            let denom = dir.perp_dot(e);
+            if denom.abs() < 1e-6 {
+                continue;
+            }
+            let w = p - origin;
+            let t = w.perp_dot(e) / denom;
+            let u = w.perp_dot(dir) / denom;
+            if t >= 0.0 && (0.0..=1.0).contains(&u) {
+                dist = dist.min(t);
+            }
+        }
+        dist
+    }
+
+    // bring back all stars
+    pub fn restart(&mut self) {
+        for star in self.stars.iter_mut() {
+            star.alive = true;
+        }
+        self.stars_left = self.stars.len();
+        self.time = 0.0;
+    }
+
+    // which stars are still around, for saving and restoring runs
+    pub fn star_state(&self) -> Vec<bool> {
+        self.stars.iter().map(|s| s.alive).collect()
+    }
+
+    pub fn set_star_state(&mut self, alive: &[bool]) {
+        for (star, &a) in self.stars.iter_mut().zip(alive) {
+            star.alive = a;
+        }
+        self.stars_left = self.stars.iter().filter(|s| s.alive).count();
+    }
+
+    pub fn alive_stars(&self) -> impl Iterator<Item = &Star> {
+        self.stars.iter().filter(|s| s.alive)
+    }
+
+    // alive stars without the flowers that are still locked
+    pub fn takeable_stars(&self) -> impl Iterator<Item = &Star> {
+        let locked = self.flowers_locked();
+        self.alive_stars().filter(move |s| !(s.flower && locked))
+    }
+
+    // mark stars collected in an older version of this level as collected
+    pub fn keep_stars(&mut self, old: &Level) {
+        for star in self.stars.iter_mut() {
+            if old.stars.iter().any(|s| !s.alive && s.pos == star.pos) && star.alive {
+                star.alive = false;
+                self.stars_left -= 1;
+            }
+        }
+    }
+
     pub fn pickup_stars(&mut self, pos: Vec2, r: f32) {
         let l = r + STAR_R;
         let l = l * l;
         for star in self.stars.iter_mut() {
-            if !star.alive {
+            if !star.alive || star.flower {
                 continue;
             }
             if (pos - star.pos).length_squared() <= l {
@@ -222,34 +807,84 @@ impl Level {
                 self.stars_left -= 1;
             }
         }
+        if self.flowers_locked() {
+            return;
+        }
+        let touched = self
+            .alive_stars()
+            .any(|s| (pos - s.pos).length_squared() <= l);
+        if touched {
+            for star in self.stars.iter_mut() {
+                star.alive = false;
+            }
+            self.stars_left = 0;
+        }
+    }
+
+    // flowers can't be taken while ordinary stars are left
+    fn flowers_locked(&self) -> bool {
+        self.alive_stars().any(|s| !s.flower)
     }
 
     pub fn update(&mut self, dt: f32) {
         self.time += dt;
     }
 
-    pub fn draw(&self, materials: &Materials) {
+    pub fn draw_wireframe(&self, color: Color) {
+        for mesh in [&self.wall_mesh, &self.lava_mesh] {
+            for [a, b, c] in mesh.triangles() {
+                draw_triangle_lines(a, b, c, 0.5, color);
+            }
+        }
+    }
+
+    // the decorations in front of or behind the level, the camera must be the one in use
+    pub fn draw_decorations(
+        &self,
+        front: bool,
+        textures: &decoration::Textures,
+        camera: &Camera2D,
+        batch: &mut fx::Batch,
+    ) {
+        batch.flush();
+        decoration::draw(
+            &self.decorations,
+            front,
+            self.parallax_origin,
+            textures,
+            camera,
+        );
+    }
+
+    // camera is the level position at the center of the view
+    // the level's shaders flush the batch, the stars are added to it
+    pub fn draw(&self, materials: &Materials, camera: Vec2, batch: &mut fx::Batch) {
+        batch.flush();
+        materials.set_uniforms(self.time, camera);
         gl_use_material(&materials.wall_material);
-        draw_mesh(&self.wall_mesh);
+        self.wall_mesh.draw();
 
         gl_use_material(&materials.lava_material);
-        draw_mesh(&self.lava_mesh);
+        self.lava_mesh.draw();
 
         gl_use_default_material();
 
-        let c = Color::new(0.8, 0.8, 0.3, 1.0);
+        let locked = self.flowers_locked();
         for star in self.stars.iter() {
             if !star.alive {
                 continue;
             }
-            let mut points = [Vec2::ZERO; 12];
-            points[0] = star.pos;
-            for i in 1..points.len() {
-                let r = if i % 2 == 0 { STAR_R } else { STAR_R * 0.5 };
-                let ang = (i as f32) * 0.2 * PI + (self.time * 3.0).sin() * 0.8;
-                points[i] = star.pos - Vec2::from_angle(ang) * r;
-            }
-            fx::draw_polygon(&points, c);
+            let c = match (star.flower, locked) {
+                (false, _) => Color::new(0.8, 0.8, 0.3, 1.0),
+                (true, false) => Color::new(0.9, 0.9, 0.9, 1.0),
+                (true, true) => Color::new(0.9, 0.9, 0.9, 0.4),
+            };
+            let points: [Vec2; 10] = std::array::from_fn(|i| {
+                let r = if i % 2 == 0 { STAR_R * 0.5 } else { STAR_R };
+                let ang = (i as f32 + 1.0) * 0.2 * PI + (self.time * 3.0).sin() * 0.8;
+                star.pos - Vec2::from_angle(ang) * r
+            });
+            batch.polygon(&points, c);
         }
     }
 }
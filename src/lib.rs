@@ -0,0 +1,85 @@
+pub mod bike;
+pub mod capture;
+pub mod debug;
+pub mod decoration;
+pub mod editor;
+pub mod elma;
+pub mod env;
+pub mod fx;
+pub mod leaderboard;
+pub mod level;
+pub mod materials;
+pub mod medals;
+pub mod particles;
+pub mod properties;
+pub mod reload;
+pub mod replay;
+pub mod rewind;
+pub mod skin;
+pub mod solver;
+pub mod speedrun;
+pub mod svg;
+pub mod tas;
+pub mod tricks;
+pub mod ui;
+pub mod versus;
+
+// FNV-1a, used to identify levels and bike profiles
+pub fn fnv1a<'a>(bytes: impl IntoIterator<Item = &'a u8>) -> u32 {
+    bytes.into_iter().fold(0x811c9dc5, |h: u32, b| {
+        (h ^ *b as u32).wrapping_mul(0x01000193)
+    })
+}
+
+#[derive(PartialEq, PartialOrd, Clone, Copy)]
+pub struct LevelTime(pub u32);
+impl LevelTime {
+    pub fn new(t: f32) -> Self {
+        Self((t * 100.0) as u32)
+    }
+    pub fn invalid() -> Self {
+        Self(0xffffffff)
+    }
+}
+impl std::str::FromStr for LevelTime {
+    type Err = String;
+    // minutes:seconds:hundredths, times come from files and other players
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "--:--:--" {
+            return Ok(Self::invalid());
+        }
+        let err = || format!("invalid time {}", s);
+        let parts: Vec<&str> = s.split(':').collect();
+        let [minutes, seconds, hundredths] = parts[..] else {
+            return Err(err());
+        };
+        let number = |part: &str, max: u32| {
+            part.parse::<u32>()
+                .ok()
+                .filter(|n| *n < max)
+                .ok_or_else(err)
+        };
+        let rest = number(seconds, 60)? * 100 + number(hundredths, 100)?;
+        let t = number(minutes, u32::MAX)?
+            .checked_mul(100 * 60)
+            .and_then(|t| t.checked_add(rest))
+            .filter(|&t| t != Self::invalid().0)
+            .ok_or_else(err)?;
+        Ok(Self(t))
+    }
+}
+impl std::fmt::Display for LevelTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0 == 0xffffffff {
+            write!(f, "--:--:--")
+        } else {
+            write!(
+                f,
+                "{:0>2}:{:0>2}:{:0>2}",
+                self.0 / (100 * 60),
+                self.0 / 100 % 60,
+                self.0 % 100,
+            )
+        }
+    }
+}
@@ -1,54 +1,134 @@
 use macroquad::prelude::*;
+use std::collections::HashMap;
 
-mod bike;
-mod fx;
-mod level;
-mod materials;
+use ::bike::{
+    bike, capture, debug, decoration, editor, fx, leaderboard, level, materials, medals, particles,
+    reload, replay, rewind, skin, speedrun, tas, tricks, ui, versus, LevelTime,
+};
 
 const W: f32 = 480.0;
 const H: f32 = 270.0;
 
-const LEVEL_FILES: [&'static str; 6] = [
-    "level1.tmj", //
-    "level2.tmj", //
-    "level3.tmj", //
-    "level4.tmj", //
-    "level5.tmj", //
-    "level6.tmj", //
+const PROFILES_FILE: &str = "assets/bikes.json";
+const THEME_FILE: &str = "assets/theme.json";
+const SKINS_FILE: &str = "assets/skins.json";
+
+struct LevelEntry {
+    file: &'static str,
+    // bronze, silver, gold and author times in seconds,
+    // overridden by the map properties of the same names
+    par_times: [f32; 4],
+}
+
+const LEVELS: [LevelEntry; 6] = [
+    LevelEntry {
+        file: "level1.tmj",
+        par_times: [10.0, 6.0, 4.0, 3.0],
+    },
+    LevelEntry {
+        file: "level2.tmj",
+        par_times: [12.0, 8.0, 5.0, 4.0],
+    },
+    LevelEntry {
+        file: "level3.tmj",
+        par_times: [30.0, 20.0, 15.0, 12.0],
+    },
+    LevelEntry {
+        file: "level4.tmj",
+        par_times: [25.0, 15.0, 10.0, 8.0],
+    },
+    LevelEntry {
+        file: "level5.tmj",
+        par_times: [30.0, 20.0, 15.0, 12.0],
+    },
+    LevelEntry {
+        file: "level6.tmj",
+        par_times: [45.0, 30.0, 22.0, 18.0],
+    },
 ];
 
-#[derive(PartialEq, PartialOrd, Clone, Copy)]
-struct LevelTime(u32);
-impl LevelTime {
-    fn new(t: f32) -> Self {
-        Self((t * 100.0) as u32)
-    }
-    fn invalid() -> Self {
-        Self(0xffffffff)
+#[derive(PartialEq, Clone, Copy)]
+enum Mode {
+    TimeTrial,
+    ScoreAttack,
+    Speedrun,
+    Versus,
+    VersusSharedStars,
+    Tas,
+}
+
+impl Mode {
+    const ALL: [Mode; 6] = [
+        Mode::TimeTrial,
+        Mode::ScoreAttack,
+        Mode::Speedrun,
+        Mode::Versus,
+        Mode::VersusSharedStars,
+        Mode::Tas,
+    ];
+
+    fn cycle(self, step: isize) -> Mode {
+        let n = Self::ALL.len() as isize;
+        let i = Self::ALL.iter().position(|&m| m == self).unwrap() as isize;
+        Self::ALL[(i + step).rem_euclid(n) as usize]
     }
 }
-impl std::fmt::Display for LevelTime {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if self.0 == 0xffffffff {
-            write!(f, "--:--:--")
-        } else {
-            write!(
-                f,
-                "{:0>2}:{:0>2}:{:0>2}",
-                self.0 / (100 * 60),
-                self.0 / 100 % 60,
-                self.0 % 100,
-            )
-        }
+
+struct Keys {
+    accelerate: KeyCode,
+    brake: KeyCode,
+    left: KeyCode,
+    right: KeyCode,
+    toggle_dir: KeyCode,
+}
+
+const PLAYER_KEYS: [Keys; 2] = [
+    Keys {
+        accelerate: KeyCode::Up,
+        brake: KeyCode::Down,
+        left: KeyCode::Left,
+        right: KeyCode::Right,
+        toggle_dir: KeyCode::Space,
+    },
+    Keys {
+        accelerate: KeyCode::W,
+        brake: KeyCode::S,
+        left: KeyCode::A,
+        right: KeyCode::D,
+        toggle_dir: KeyCode::LeftShift,
+    },
+];
+
+fn read_input(keys: &Keys) -> bike::Input {
+    bike::Input {
+        toggle_dir: is_key_down(keys.toggle_dir),
+        wheel: match (is_key_down(keys.brake), is_key_down(keys.accelerate)) {
+            (true, false) => bike::WheelInput::Break,
+            (false, true) => bike::WheelInput::Accelerate,
+            _ => bike::WheelInput::None,
+        },
+        jump: match (is_key_down(keys.left), is_key_down(keys.right)) {
+            (true, false) => Some(bike::Direction::Left),
+            (false, true) => Some(bike::Direction::Right),
+            _ => None,
+        },
     }
 }
 
+// best times and scores reached with one bike profile
+struct Records {
+    times: [LevelTime; LEVELS.len()],
+    scores: [u32; LEVELS.len()],
+}
+
 #[derive(PartialEq)]
 enum GameState {
     LevelMenu,
     Playing,
     LevelCompleted,
     GameOver,
+    RaceOver,
+    Editor,
 }
 
 struct Game {
@@ -58,35 +138,78 @@ struct Game {
     physics_time: f32,
     level: level::Level,
     bike: bike::Bike,
+    tricks: tricks::TrickDetector,
+    debug: debug::DebugOverlay,
     materials: materials::Materials,
-    level_times: [LevelTime; LEVEL_FILES.len()],
+    // images of the level decorations
+    textures: decoration::Textures,
+    // colors and shaders for levels that don't set their own
+    pack_theme: materials::Theme,
+    particles: particles::Particles,
+    // shapes of the frame, drawn together until a texture or shader comes up
+    batch: fx::Batch,
+    mode: Mode,
+    speedrun: Option<speedrun::Speedrun>,
+    versus: Option<versus::Versus>,
+    tas: Option<tas::Tas>,
+    rewind: rewind::Rewind,
+    // inputs of the current run
+    replay: replay::Replay,
+    leaderboard: Option<leaderboard::Client>,
+    // hashes of the level files, for looking up leaderboard times
+    level_hashes: [u32; LEVELS.len()],
+    // title and designer from the level properties, for the menu
+    level_titles: [String; LEVELS.len()],
+    editor: Option<editor::Editor>,
+    profiles: Vec<bike::Profile>,
+    profile: usize,
+    skins: Vec<skin::Skin>,
+    skin: usize,
+    // records of the other profiles, by profile hash
+    stored_records: HashMap<u32, Records>,
+    splits: speedrun::Splits,
+    level_times: [LevelTime; LEVELS.len()],
+    level_scores: [u32; LEVELS.len()],
+    par_times: [medals::ParTimes; LEVELS.len()],
     level_index: usize,
+    watcher: Option<reload::Watcher>,
+    // keep playing on when the level file changes, instead of restarting
+    keep_bike_on_reload: bool,
+    reload_time: Option<f64>,
+    load_error: Option<String>,
+    // save the screen after drawing this frame, and what came of the last one
+    take_screenshot: bool,
+    screenshot_message: Option<(String, f64)>,
     running: bool,
 }
 
-fn mix_color(a: Color, b: Color, x: f32) -> Color {
-    Color::from_vec(a.to_vec() * (1.0 - x) + b.to_vec() * x)
+fn level_title(level: &level::Level) -> String {
+    let meta = &level.meta;
+    let title = match (&meta.title, &meta.designer) {
+        (Some(t), Some(d)) => format!("{} BY {}", t, d),
+        (Some(t), None) => t.clone(),
+        (None, Some(d)) => format!("BY {}", d),
+        (None, None) => String::new(),
+    };
+    title.to_uppercase()
 }
 
-fn draw_text_mono(text: &str, x: f32, y: f32, params: TextParams) {
-    let scale = (params.font_size as f32) * params.font_scale * 0.1;
-    let mut x = x;
-    for c in text.chars() {
-        let o = match c {
-            ':' => 1.0 * scale,
-            '1' => 1.5 * scale,
-            _ => 0.0,
-        };
-        draw_text_ex(&c.to_string(), x + o, y, params.clone());
-        x += match c {
-            ':' => 3.0 * scale,
-            _ => 5.0 * scale,
-        };
-    }
+fn mix_color(a: Color, b: Color, x: f32) -> Color {
+    Color::from_vec(a.to_vec() * (1.0 - x) + b.to_vec() * x)
 }
 
 impl Game {
     async fn new() -> Game {
+        let (profiles, load_error) = match bike::load_profiles(PROFILES_FILE).await {
+            Ok(profiles) => (profiles, None),
+            Err(e) => (
+                vec![bike::Profile {
+                    name: "standard".to_string(),
+                    params: Default::default(),
+                }],
+                Some(format!("{}: {}", PROFILES_FILE, e)),
+            ),
+        };
         let mut game = Game {
             canvas_size: Default::default(),
             state: GameState::LevelMenu,
@@ -94,13 +217,79 @@ impl Game {
             physics_time: 0.0,
             level: Default::default(),
             bike: Default::default(),
+            tricks: Default::default(),
+            debug: Default::default(),
             materials: materials::Materials::load(),
+            textures: Default::default(),
+            pack_theme: Default::default(),
+            particles: Default::default(),
+            batch: Default::default(),
+            mode: Mode::TimeTrial,
+            speedrun: None,
+            versus: None,
+            tas: None,
+            rewind: Default::default(),
+            replay: replay::Replay::new("", 0),
+            leaderboard: leaderboard::Client::from_env(),
+            level_hashes: [0; LEVELS.len()],
+            level_titles: Default::default(),
+            editor: None,
+            profiles,
+            profile: 0,
+            skins: vec![],
+            skin: 0,
+            stored_records: HashMap::new(),
+            splits: speedrun::Splits::new(&LEVELS.map(|l| l.file)),
             level_times: std::array::from_fn(|_| LevelTime::invalid()),
+            level_scores: [0; LEVELS.len()],
+            par_times: std::array::from_fn(|i| medals::ParTimes::from_secs(LEVELS[i].par_times)),
             level_index: 0,
+            watcher: None,
+            keep_bike_on_reload: true,
+            reload_time: None,
+            load_error: None,
+            take_screenshot: false,
+            screenshot_message: None,
             running: true,
         };
+        for (i, entry) in LEVELS.iter().enumerate() {
+            let path = format!("assets/{}", entry.file);
+            if let Ok(level) = level::Level::load(&path).await {
+                game.level_hashes[i] = level.hash;
+                game.level_titles[i] = level_title(&level);
+                if let Some(t) = level.par_times {
+                    game.par_times[i] = medals::ParTimes::from_secs(t);
+                }
+            }
+        }
+        game.load_splits();
+        let mut load_error = load_error.or(game.load_error.take());
+        game.skins = match skin::load_skins(SKINS_FILE).await {
+            Ok(skins) => skins,
+            Err(e) => {
+                load_error = load_error.or(Some(format!("{}: {}", SKINS_FILE, e)));
+                vec![Default::default()]
+            }
+        };
+        let skin_textures = game.skins.iter().flat_map(|s| s.textures());
+        if let Err(e) = game.textures.load_paths(skin_textures).await {
+            load_error = load_error.or(Some(e));
+        }
+        // a level pack doesn't need a theme
+        if let Ok(string) = load_string(THEME_FILE).await {
+            match materials::Theme::parse_json(&string) {
+                Ok(mut theme) => {
+                    theme.resolve_paths("assets");
+                    game.pack_theme = theme;
+                }
+                Err(e) => load_error = load_error.or(Some(format!("{}: {}", THEME_FILE, e))),
+            }
+        }
         game.reset_level().await;
         game.state = GameState::LevelMenu;
+        if game.load_error.is_none() {
+            game.load_error = load_error;
+        }
         game
     }
 
@@ -108,10 +297,171 @@ impl Game {
         self.state = GameState::Playing;
         self.time = 0.0;
         self.physics_time = 0.0;
-        self.level = level::Level::load(&format!("assets/{}", LEVEL_FILES[self.level_index]))
-            .await
-            .unwrap();
-        self.bike = bike::Bike::new(self.level.start);
+        let path = format!("assets/{}", LEVELS[self.level_index].file);
+        self.bike = Default::default();
+        self.tricks = Default::default();
+        self.versus = None;
+        self.tas = None;
+        self.rewind = Default::default();
+        // a finished run is over, playing on doesn't add to it
+        if self.speedrun.as_ref().is_some_and(|run| run.finished()) {
+            self.speedrun = None;
+        }
+        self.replay = replay::Replay::new(LEVELS[self.level_index].file, self.profile_hash());
+        self.replay.skin = self.skins[self.skin].name.clone();
+        self.particles.clear();
+
+        // test the edited level
+        if let Some(editor) = &mut self.editor {
+            match editor.build_level() {
+                Ok(level) => {
+                    self.level = level;
+                    self.bike = bike::Bike::new(self.level.start, self.bike_params());
+                }
+                Err(e) => {
+                    editor.set_message(format!("ERROR: {}", e));
+                    self.state = GameState::Editor;
+                }
+            }
+            return;
+        }
+
+        if self.watcher.as_ref().is_none_or(|w| w.path != path) {
+            self.watcher = Some(reload::Watcher::new(&path));
+        }
+        match level::Level::load(&path).await {
+            Ok(level) => {
+                self.level = level;
+                self.load_error = self.load_level_assets().await.err();
+            }
+            // nothing to play, and nothing to record for this level
+            Err(e) => {
+                self.load_error = Some(format!("{}: {}", path, e));
+                self.state = GameState::LevelMenu;
+                self.speedrun = None;
+                return;
+            }
+        }
+        self.level_hashes[self.level_index] = self.level.hash;
+        self.level_titles[self.level_index] = level_title(&self.level);
+        if let Some(t) = self.level.par_times {
+            self.par_times[self.level_index] = medals::ParTimes::from_secs(t);
+        }
+        let params = self.bike_params();
+        self.bike = bike::Bike::new(self.level.start, params);
+        self.versus = match self.mode {
+            // the second player gets a copy with stars of their own
+            Mode::Versus => match level::Level::load(&path).await {
+                Ok(level) => Some(versus::Versus::new(Some(level), &self.level, params)),
+                Err(e) => {
+                    self.load_error = Some(format!("{}: {}", path, e));
+                    self.state = GameState::LevelMenu;
+                    return;
+                }
+            },
+            Mode::VersusSharedStars => Some(versus::Versus::new(None, &self.level, params)),
+            _ => None,
+        };
+        if self.mode == Mode::Tas {
+            let file = LEVELS[self.level_index].file;
+            let mut tas = tas::Tas::new(file, self.profile_hash());
+            tas.replay.skin = self.replay.skin.clone();
+            self.tas = Some(tas);
+        }
+    }
+
+    // images and shaders of the current level
+    async fn load_level_assets(&mut self) -> Result<(), String> {
+        let textures = self.textures.load(&self.level.decorations).await;
+        let theme = self.level.theme.clone().or(&self.pack_theme);
+        textures.and(self.materials.set_theme(theme).await)
+    }
+
+    // pick up changes to the level file
+    async fn reload_level(&mut self) {
+        let path = format!("assets/{}", LEVELS[self.level_index].file);
+        let mut level = match level::Level::load(&path).await {
+            Ok(level) => level,
+            Err(e) => {
+                self.load_error = Some(format!("{}: {}", path, e));
+                return;
+            }
+        };
+        self.load_error = None;
+        self.reload_time = Some(get_time());
+        if self.keep_bike_on_reload && self.state == GameState::Playing && self.versus.is_none() {
+            level.keep_stars(&self.level);
+            if let Some(t) = level.par_times {
+                self.par_times[self.level_index] = medals::ParTimes::from_secs(t);
+            }
+            self.level_hashes[self.level_index] = level.hash;
+            self.level_titles[self.level_index] = level_title(&level);
+            self.level = level;
+            // bike.<field> and gravity may have changed
+            self.bike.params = self.bike_params();
+            self.load_error = self.load_level_assets().await.err();
+        } else {
+            self.reset_level().await;
+        }
+    }
+
+    fn medal(&self, i: usize) -> Option<medals::Medal> {
+        self.par_times[i].medal(self.level_times[i])
+    }
+
+    // the best medal of a level, with any bike
+    fn best_medal(&self, i: usize) -> Option<medals::Medal> {
+        let stored = self
+            .stored_records
+            .values()
+            .map(|r| self.par_times[i].medal(r.times[i]));
+        std::iter::once(self.medal(i)).chain(stored).flatten().max()
+    }
+
+    // a level is unlocked once the previous one has earned a medal, with any bike
+    fn unlocked(&self, i: usize) -> bool {
+        i == 0 || self.best_medal(i - 1).is_some()
+    }
+
+    fn skin_unlocked(&self, i: usize) -> bool {
+        let medals: Vec<_> = (0..LEVELS.len()).map(|l| self.best_medal(l)).collect();
+        self.skins[i].unlocked(&medals)
+    }
+
+    fn profile_hash(&self) -> u32 {
+        self.profiles[self.profile].params.hash()
+    }
+
+    // the selected profile with the overrides of the current level
+    fn bike_params(&self) -> bike::BikeParams {
+        self.level.bike_params(self.profiles[self.profile].params)
+    }
+
+    // times are only comparable with the same bike, so each profile has its own records
+    fn select_profile(&mut self, profile: usize) {
+        let records = Records {
+            times: self.level_times,
+            scores: self.level_scores,
+        };
+        self.stored_records.insert(self.profile_hash(), records);
+        self.profile = profile;
+        let hash = self.profile_hash();
+        let records = self.stored_records.remove(&hash).unwrap_or(Records {
+            times: std::array::from_fn(|_| LevelTime::invalid()),
+            scores: [0; LEVELS.len()],
+        });
+        self.level_times = records.times;
+        self.level_scores = records.scores;
+        self.load_splits();
+    }
+
+    fn load_splits(&mut self) {
+        let levels = LEVELS.map(|l| l.file);
+        let path = speedrun::splits_file(self.profile_hash());
+        self.splits = speedrun::Splits::load(&levels, &path).unwrap_or_else(|e| {
+            self.load_error = Some(e);
+            speedrun::Splits::new(&levels)
+        });
     }
 
     async fn update(&mut self) {
@@ -125,6 +475,34 @@ impl Game {
         let dt = get_frame_time();
         self.time += dt;
 
+        let profile = self.profile_hash();
+        if let Some(lb) = &mut self.leaderboard {
+            lb.poll();
+            if self.state == GameState::LevelMenu {
+                lb.want_top(self.level_hashes[self.level_index], profile);
+            }
+        }
+
+        if is_key_pressed(KeyCode::F3) {
+            self.debug.enabled = !self.debug.enabled;
+        }
+        if is_key_pressed(KeyCode::F12) {
+            self.take_screenshot = true;
+        }
+
+        // hot reload
+        if is_key_pressed(KeyCode::F6) {
+            self.keep_bike_on_reload = !self.keep_bike_on_reload;
+        }
+        let in_level = self.state != GameState::LevelMenu && self.state != GameState::Editor;
+        if in_level && self.editor.is_none() {
+            if let Some(watcher) = &mut self.watcher {
+                if watcher.changed(dt) {
+                    self.reload_level().await;
+                }
+            }
+        }
+
         // go back to level menu
         if is_key_pressed(KeyCode::Escape) {
             if self.state == GameState::LevelMenu {
@@ -132,20 +510,71 @@ impl Game {
                 {
                     self.running = false;
                 }
+            } else if self.state == GameState::Editor {
+                if self.editor.as_mut().is_none_or(|e| e.leave()) {
+                    self.state = GameState::LevelMenu;
+                    self.editor = None;
+                }
+            } else if self.editor.is_some() {
+                // stop testing the edited level
+                self.state = GameState::Editor;
             } else {
                 self.state = GameState::LevelMenu;
+                self.speedrun = None;
+                self.versus = None;
+                self.tas = None;
             }
         }
 
-        // reset
-        if is_key_pressed(KeyCode::Enter) {
-            if self.state == GameState::LevelCompleted {
+        // reset, the editor tests its level with P and a tool-assisted run is
+        // restarted by rewinding
+        let in_tas = self.state == GameState::Playing && self.tas.is_some();
+        if is_key_pressed(KeyCode::Enter) && self.state != GameState::Editor && !in_tas {
+            if self.state == GameState::LevelCompleted && self.editor.is_some() {
+                self.state = GameState::Editor;
+            } else if self.state == GameState::LevelCompleted || self.state == GameState::RaceOver {
                 self.state = GameState::LevelMenu;
-            } else {
+                self.speedrun = None;
+                self.versus = None;
+            } else if self.state == GameState::LevelMenu && self.mode == Mode::Speedrun {
+                // a run goes through all levels, so they all need to be unlocked
+                if self.unlocked(LEVELS.len() - 1) {
+                    let path = speedrun::splits_file(self.profile_hash());
+                    self.speedrun = Some(speedrun::Speedrun::new(self.splits.clone(), path));
+                    self.level_index = 0;
+                    self.reset_level().await;
+                }
+            } else if self.unlocked(self.level_index) {
                 self.reset_level().await;
             }
         }
 
+        // scrub back while the key is held, also after a crash
+        let can_rewind = matches!(self.mode, Mode::TimeTrial | Mode::ScoreAttack)
+            && matches!(self.state, GameState::Playing | GameState::GameOver);
+        if can_rewind && is_key_down(KeyCode::R) {
+            if let Some(snapshot) = self.rewind.back() {
+                self.bike = snapshot.bike;
+                self.level.set_star_state(&snapshot.stars);
+                self.tricks = snapshot.tricks;
+                self.physics_time = snapshot.time;
+                self.time = snapshot.time;
+                self.state = GameState::Playing;
+            }
+            return;
+        }
+
+        // particles pick up what happened in the last frame
+        if in_level {
+            let views = match &self.versus {
+                Some(vs) => vec![(&self.bike, &self.level), (&vs.bike, vs.level(&self.level))],
+                None => vec![(&self.bike, &self.level)],
+            };
+            let view_size = vec2(self.canvas_size.x / views.len() as f32, self.canvas_size.y);
+            self.particles
+                .update(dt, &views, view_size, &self.materials.theme);
+        }
+
         match self.state {
             GameState::LevelMenu => {
                 if is_key_pressed(KeyCode::Up) && self.level_index > 0 {
@@ -154,37 +583,126 @@ impl Game {
                 if is_key_pressed(KeyCode::Down) && self.level_index < self.level_times.len() - 1 {
                     self.level_index += 1;
                 }
+                if is_key_pressed(KeyCode::Left) {
+                    self.mode = self.mode.cycle(-1);
+                }
+                if is_key_pressed(KeyCode::Right) {
+                    self.mode = self.mode.cycle(1);
+                }
+                if is_key_pressed(KeyCode::B) {
+                    self.select_profile((self.profile + 1) % self.profiles.len());
+                }
+                if is_key_pressed(KeyCode::S) {
+                    // the next skin that's unlocked
+                    let n = self.skins.len();
+                    if let Some(i) = (1..n)
+                        .map(|k| (self.skin + k) % n)
+                        .find(|&i| self.skin_unlocked(i))
+                    {
+                        self.skin = i;
+                    }
+                }
+                if is_key_pressed(KeyCode::E) {
+                    let path = format!("assets/{}", LEVELS[self.level_index].file);
+                    match editor::Editor::load(&path).await {
+                        Ok(editor) => {
+                            self.editor = Some(editor);
+                            self.state = GameState::Editor;
+                            self.load_error = None;
+                        }
+                        Err(e) => self.load_error = Some(format!("{}: {}", path, e)),
+                    }
+                }
+            }
+            GameState::Editor => {
+                if let Some(editor) = &mut self.editor {
+                    editor.update(self.canvas_size);
+                }
+                if is_key_pressed(KeyCode::P) {
+                    self.reset_level().await;
+                }
             }
             GameState::Playing => {
                 self.level.update(dt);
+                if let Some(l) = self.versus.as_mut().and_then(|vs| vs.level.as_mut()) {
+                    l.update(dt);
+                }
 
-                let input = bike::Input {
-                    toggle_dir: is_key_down(KeyCode::Space),
-                    wheel: match (is_key_down(KeyCode::Down), is_key_down(KeyCode::Up)) {
-                        (true, false) => bike::WheelInput::Break,
-                        (false, true) => bike::WheelInput::Accelerate,
-                        _ => bike::WheelInput::None,
-                    },
-                    jump: match (is_key_down(KeyCode::Left), is_key_down(KeyCode::Right)) {
-                        (true, false) => Some(bike::Direction::Left),
-                        (false, true) => Some(bike::Direction::Right),
-                        _ => None,
-                    },
-                };
+                let input = read_input(&PLAYER_KEYS[0]);
+
+                if let Some(tas) = &mut self.tas {
+                    tas.update(dt, &input, &mut self.bike, &mut self.level);
+                    self.physics_time = tas.time();
+                    return;
+                }
 
-                let dt = 0.0002;
+                let dt = bike::PHYSICS_DT;
+
+                let mut steps = 0;
+                if let Some(vs) = &mut self.versus {
+                    let input2 = read_input(&PLAYER_KEYS[1]);
+                    while self.physics_time + dt < self.time {
+                        self.physics_time += dt;
+                        steps += 1;
+                        let inputs = [&input, &input2];
+                        if vs.update(
+                            dt,
+                            self.physics_time,
+                            &mut self.bike,
+                            &mut self.level,
+                            inputs,
+                        ) {
+                            self.state = GameState::RaceOver;
+                            self.time = 0.0;
+                            break;
+                        }
+                    }
+                    self.debug.record(steps, &self.bike);
+                    return;
+                }
+
+                let mut next_level = false;
                 while self.physics_time + dt < self.time {
                     self.physics_time += dt;
+                    steps += 1;
+                    if let Some(run) = &mut self.speedrun {
+                        run.time += dt as f64;
+                    }
                     self.bike.update(dt, &mut self.level, &input);
+                    self.replay.push(&input, 1);
+                    self.tricks.update(dt, &self.bike);
+                    self.rewind
+                        .record(self.physics_time, &self.bike, &self.level, &self.tricks);
 
                     if self.level.stars_left == 0 {
                         self.state = GameState::LevelCompleted;
                         self.time = 0.0;
+                        self.tricks.finish(&self.bike);
 
-                        let t = &mut self.level_times[self.level_index];
-                        let new_t = LevelTime::new(self.physics_time);
-                        if new_t < *t {
-                            *t = new_t;
+                        // the same time the leaderboard gets when it plays the replay
+                        self.physics_time = self.replay.ticks() as f32 * dt;
+
+                        // edited levels and rewound runs don't count
+                        if self.editor.is_none() && !self.rewind.used {
+                            let t = &mut self.level_times[self.level_index];
+                            let new_t = self.replay.time();
+                            if new_t < *t {
+                                *t = new_t;
+                            }
+                            if let Some(lb) = &mut self.leaderboard {
+                                lb.status = None;
+                                lb.submit(&self.replay, self.level.hash, new_t);
+                            }
+                            let s = &mut self.level_scores[self.level_index];
+                            *s = (*s).max(self.tricks.points);
+                        }
+
+                        if let Some(run) = &mut self.speedrun {
+                            if let Err(e) = run.split() {
+                                self.load_error = Some(e);
+                            }
+                            self.splits = run.pb.clone();
+                            next_level = !run.finished();
                         }
                         break;
                     }
@@ -194,12 +712,51 @@ impl Game {
                         break;
                     }
                 }
+
+                self.debug.record(steps, &self.bike);
+
+                // carry on with the next level of the run
+                if next_level {
+                    self.level_index += 1;
+                    self.reset_level().await;
+                }
             }
             _ => {}
         }
     }
 
-    fn draw(&self) {
+    // the bike and level of each player
+    fn views(&self) -> Vec<(&bike::Bike, &level::Level)> {
+        match &self.versus {
+            Some(vs) => vec![(&self.bike, &self.level), (&vs.bike, vs.level(&self.level))],
+            None => vec![(&self.bike, &self.level)],
+        }
+    }
+
+    // saves what was just drawn, if asked to
+    fn screenshot(&mut self) {
+        if !std::mem::take(&mut self.take_screenshot) {
+            return;
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let path = capture::screenshot_path();
+            let text = match std::fs::write(&path, capture::screenshot().png()) {
+                Ok(()) => format!("SAVED {}", path.to_uppercase()),
+                Err(e) => format!("ERROR: {}", e),
+            };
+            self.screenshot_message = Some((text, get_time()));
+        }
+    }
+
+    fn draw(&mut self) {
+        // taken out while drawing, the views borrow the rest of the game
+        let mut batch = std::mem::take(&mut self.batch);
+        self.draw_frame(&mut batch);
+        self.batch = batch;
+    }
+
+    fn draw_frame(&self, batch: &mut fx::Batch) {
         let mut cam = Camera2D::from_display_rect(Rect::new(
             0.0,
             0.0,
@@ -207,114 +764,422 @@ impl Game {
             self.canvas_size.y,
         ));
         cam.zoom.y = cam.zoom.y.abs();
-        cam.target = self.bike.frame.pos;
         set_camera(&cam);
 
         // background
+        let background = self.materials.theme.background.unwrap();
         match self.state {
             GameState::GameOver => {
                 // flash
                 let x = (self.time * 5.0).min(1.0);
-                clear_background(mix_color(
-                    Color::new(1.0, 0.0, 0.0, 1.0),
-                    Color::from_rgba(10, 12, 15, 255),
-                    x,
-                ));
+                clear_background(mix_color(Color::new(1.0, 0.0, 0.0, 1.0), background, x));
             }
             _ => {
-                clear_background(Color::from_rgba(10, 12, 15, 255));
+                clear_background(background);
             }
         }
 
-        self.level.draw(&self.materials);
-        self.bike.draw();
+        if let (GameState::Editor, Some(editor)) = (&self.state, &self.editor) {
+            editor.draw(self.canvas_size, &self.materials, &self.textures, batch);
+            return;
+        }
 
-        // labels
-        let tp_small = {
-            let font_scale = macroquad::text::camera_font_scale(11.0);
-            TextParams {
-                font: Some(&self.materials.font),
-                font_size: font_scale.0,
-                font_scale: font_scale.1,
-                ..TextParams::default()
+        // split the screen between players
+        let views = self.views();
+        let n = views.len() as f32;
+        let view_size = vec2(self.canvas_size.x / n, self.canvas_size.y);
+        for (i, (bike, level)) in views.iter().enumerate() {
+            let mut cam =
+                Camera2D::from_display_rect(Rect::new(0.0, 0.0, view_size.x, view_size.y));
+            cam.zoom.y = cam.zoom.y.abs();
+            if views.len() > 1 {
+                // viewports are in pixels
+                let dpi = screen_dpi_scale();
+                let w = screen_width() * dpi / n;
+                let h = screen_height() * dpi;
+                cam.viewport = Some(((i as f32 * w) as i32, 0, w as i32, h as i32));
             }
-        };
+            cam.target = bike.frame.pos;
+            set_camera(&cam);
 
-        let tp_menu = {
-            let font_scale = macroquad::text::camera_font_scale(20.0);
-            TextParams {
-                font: Some(&self.materials.font),
-                font_size: font_scale.0,
-                font_scale: font_scale.1,
-                ..TextParams::default()
+            level.draw_decorations(false, &self.textures, &cam, batch);
+            level.draw(&self.materials, cam.target, batch);
+            for (b, _) in views.iter() {
+                b.draw(&self.skins[self.skin], &self.textures, batch);
             }
-        };
-
-        let tp_big = {
-            let font_scale = macroquad::text::camera_font_scale(40.0);
-            TextParams {
-                font: Some(&self.materials.font),
-                font_size: font_scale.0,
-                font_scale: font_scale.1,
-                ..TextParams::default()
+            self.particles.draw(batch);
+            level.draw_decorations(true, &self.textures, &cam, batch);
+            if self.debug.enabled {
+                self.debug.draw_world(bike, level);
             }
-        };
 
-        // show star count and time
+            // show star count
+            cam.target = view_size * 0.5;
+            set_camera(&cam);
+            let ui = ui::Ui::new(&self.materials.font, view_size);
+            let stars = level.stars.len() - level.stars_left;
+            ui.mono(
+                &format!("{}/{}", stars, level.stars.len()),
+                ui.at(ui::TOP_LEFT, vec2(5.0, 12.0)),
+                ui::Align::Left,
+                &ui.small,
+            );
+        }
+
+        // the rest is laid out on the whole canvas
         cam.target = self.canvas_size * 0.5;
         set_camera(&cam);
-        draw_text_mono(
-            &format!(
-                "{}/{}",
-                self.level.stars.len() - self.level.stars_left,
-                self.level.stars.len()
-            ),
-            5.0,
-            12.0,
-            tp_small.clone(),
-        );
-        draw_text_mono(
+        let ui = ui::Ui::new(&self.materials.font, self.canvas_size);
+        let center = ui.at(ui::CENTER, Vec2::ZERO);
+
+        // show time
+        if views.len() > 1 {
+            let x = self.canvas_size.x * 0.5;
+            draw_line(x, 0.0, x, self.canvas_size.y, 1.0, background);
+        }
+        ui.mono(
             &LevelTime::new(self.physics_time).to_string(),
-            self.canvas_size.x - 42.0,
-            12.0,
-            tp_small.clone(),
+            ui.at(ui::TOP_RIGHT, vec2(-5.0, 12.0)),
+            ui::Align::Right,
+            &ui.small,
         );
+        if self.mode == Mode::ScoreAttack {
+            ui.mono(
+                &self.tricks.points.to_string(),
+                ui.at(ui::TOP_RIGHT, vec2(-5.0, 24.0)),
+                ui::Align::Right,
+                &ui.small,
+            );
+        }
 
-        cam.target = Vec2::ZERO;
-        set_camera(&cam);
+        if self.debug.enabled {
+            self.debug.draw_hud(&self.bike, &ui);
+        }
+
+        // live splits
+        if let Some(run) = &self.speedrun {
+            ui.mono(
+                &run.current().to_string(),
+                ui.at(ui::TOP_RIGHT, vec2(-5.0, 24.0)),
+                ui::Align::Right,
+                &ui.small,
+            );
+            for (i, split) in run.pb.0.iter().enumerate() {
+                let y = 28.0 + (i as f32) * 12.0;
+                let (t, color) = match run.splits.get(i) {
+                    Some(&t) if run.golds[i] => (t, Color::new(0.8, 0.8, 0.3, 1.0)),
+                    Some(&t) if t < split.pb => (t, Color::new(0.4, 0.9, 0.4, 1.0)),
+                    Some(&t) => (t, Color::new(0.9, 0.4, 0.4, 1.0)),
+                    None => (split.pb, Color::new(1.0, 1.0, 1.0, 0.5)),
+                };
+                let tp = TextParams {
+                    color,
+                    ..ui.small.clone()
+                };
+                let at = |x: f32| ui.at(ui::TOP_LEFT, vec2(x, y));
+                ui.mono(&format!("{}", i + 1), at(5.0), ui::Align::Left, &tp);
+                ui.mono(&t.to_string(), at(15.0), ui::Align::Left, &tp);
+                if i < run.splits.len() && split.pb != LevelTime::invalid() {
+                    let delta = speedrun::format_delta(t, split.pb);
+                    ui.mono(&delta, at(58.0), ui::Align::Left, &tp);
+                }
+            }
+        }
+
+        if let Some(tas) = &self.tas {
+            tas.draw_hud(&self.bike, &self.level, &ui);
+        }
+
+        // trick popups
+        for (i, (trick, age)) in self.tricks.popups().enumerate() {
+            let y = 40.0 + (i as f32) * 14.0 - age * 10.0;
+            let a = (tricks::POPUP_DURATION - age).min(1.0);
+            ui.text(
+                &format!("{} +{}", trick.kind, trick.points),
+                ui.at(ui::TOP, vec2(0.0, y)),
+                ui::Align::Center,
+                &TextParams {
+                    color: Color::new(0.8, 0.8, 0.3, a),
+                    ..ui.small.clone()
+                },
+            );
+        }
+
+        // lines centered on the canvas
+        let centered = |text: &str, y: f32, style: &TextParams| {
+            ui.text_fit(
+                text,
+                center + vec2(0.0, y),
+                ui::Align::Center,
+                self.canvas_size.x - 10.0,
+                style,
+            );
+        };
+        // a label left of the center and a number right of it
+        let label = |text: &str, value: &str, y: f32, style: &TextParams| {
+            ui.text(text, center + vec2(-10.0, y), ui::Align::Right, style);
+            ui.mono(value, center + vec2(10.0, y), ui::Align::Left, style);
+        };
 
         match self.state {
             GameState::GameOver => {
-                draw_text_ex("OUCH!", -70.0, -50.0, tp_big.clone());
+                centered("OUCH!", -50.0, &ui.big);
+                if matches!(self.mode, Mode::TimeTrial | Mode::ScoreAttack) {
+                    centered("HOLD R TO REWIND", -20.0, &ui.small);
+                }
             }
             GameState::LevelCompleted => {
-                draw_text_ex("WELL DONE!", -117.0, -50.0, tp_big.clone());
+                centered("WELL DONE!", -50.0, &ui.big);
+                if self.mode == Mode::ScoreAttack {
+                    label("SCORE", &self.tricks.points.to_string(), -20.0, &ui.menu);
+                }
+                if let Some(run) = &self.speedrun {
+                    label("RUN", &run.current().to_string(), -20.0, &ui.menu);
+                    if run.new_pb {
+                        centered("NEW PERSONAL BEST", 30.0, &ui.small);
+                    }
+                }
+                let profile = &self.profiles[self.profile];
+                let text = format!(
+                    "BIKE {} {:08X}",
+                    profile.name.to_uppercase(),
+                    self.profile_hash()
+                );
+                centered(&text, 44.0, &ui.small);
+                let status = self.leaderboard.as_ref().and_then(|lb| lb.status.as_ref());
+                if let Some(text) = status {
+                    centered(text, 68.0, &ui.small);
+                }
+                if self.rewind.used {
+                    centered("REWOUND, NOT A RECORD", 56.0, &ui.small);
+                }
+                let t = LevelTime::new(self.physics_time);
+                if let Some(medal) = self.par_times[self.level_index].medal(t) {
+                    let tp = TextParams {
+                        color: medal.color(),
+                        ..ui.menu.clone()
+                    };
+                    centered(&format!("{} MEDAL", medal), 10.0, &tp);
+                }
             }
-            GameState::LevelMenu => {
-                draw_text_ex("BIKE", -52.0, -80.0, tp_big.clone());
-                for (i, &t) in self.level_times.iter().enumerate() {
-                    let y = -40.0 + (i as f32) * 24.0;
-                    // cursor
-                    if i == self.level_index {
-                        let c = Color::new(0.8, 0.8, 0.3, 0.3);
-                        draw_rectangle(-130.0, y - 19.0, 260.0, 24.0, c);
+            GameState::RaceOver => {
+                if let Some(vs) = &self.versus {
+                    let text = match vs.winner() {
+                        Some(i) => format!("PLAYER {} WINS!", i + 1),
+                        None => "DRAW!".to_string(),
+                    };
+                    centered(&text, -50.0, &ui.big);
+                    for (i, racer) in vs.racers.iter().enumerate() {
+                        let y = -10.0 + (i as f32) * 24.0;
+                        let at = |x: f32| center + vec2(x, y);
+                        let left = ui::Align::Left;
+                        ui.text(&format!("P{}", i + 1), at(-120.0), left, &ui.menu);
+                        match racer.finish {
+                            Some(t) => ui.mono(&t.to_string(), at(-80.0), left, &ui.menu),
+                            None => ui.text("DNF", at(-80.0), left, &ui.menu),
+                        }
+                        ui.mono(
+                            &racer.stars.to_string(),
+                            at(40.0),
+                            ui::Align::Right,
+                            &ui.menu,
+                        );
+                        ui.text("STARS", at(48.0), left, &ui.menu);
                     }
-                    draw_text_ex("LEVEL", -120.0, y, tp_menu.clone());
-                    draw_text_mono(&format!("{:>2}", i + 1), -70.0, y, tp_menu.clone());
-                    draw_text_mono(&t.to_string(), 48.0, y, tp_menu.clone());
                 }
             }
+            GameState::LevelMenu => self.draw_level_menu(&ui),
             _ => {}
         }
+
+        // hot reload status
+        let bottom_line = |text: &str, style: &TextParams| {
+            ui.text_fit(
+                text,
+                ui.at(ui::BOTTOM_LEFT, vec2(5.0, -5.0)),
+                ui::Align::Left,
+                self.canvas_size.x - 10.0,
+                style,
+            );
+        };
+        if let Some(error) = &self.load_error {
+            let chars: Vec<char> = error.chars().collect();
+            let lines: Vec<String> = chars.chunks(80).map(|c| c.iter().collect()).collect();
+            let h = lines.len() as f32 * 12.0 + 6.0;
+            let y = self.canvas_size.y - h;
+            draw_rectangle(
+                0.0,
+                y,
+                self.canvas_size.x,
+                h,
+                Color::new(0.0, 0.0, 0.0, 0.8),
+            );
+            let tp = TextParams {
+                color: Color::new(1.0, 0.4, 0.4, 1.0),
+                ..ui.small.clone()
+            };
+            for (i, line) in lines.iter().enumerate() {
+                ui.text_fit(
+                    line,
+                    vec2(5.0, y + 12.0 + i as f32 * 12.0),
+                    ui::Align::Left,
+                    self.canvas_size.x - 10.0,
+                    &tp,
+                );
+            }
+        } else if let Some((text, _)) = self
+            .screenshot_message
+            .as_ref()
+            .filter(|(_, t)| get_time() - t < 2.0)
+        {
+            bottom_line(text, &ui.small);
+        } else if self.reload_time.is_some_and(|t| get_time() - t < 1.0) {
+            let text = if self.keep_bike_on_reload {
+                "LEVEL RELOADED"
+            } else {
+                "LEVEL RELOADED (RESTART)"
+            };
+            bottom_line(text, &ui.small);
+        }
+    }
+
+    // Title and mode at the top, the bike and skin at the bottom and as many
+    // levels as fit in between.
+    fn draw_level_menu(&self, ui: &ui::Ui) {
+        let x = ui.at(ui::CENTER, Vec2::ZERO).x;
+        let footer = |text: &str, y: f32| {
+            ui.text_fit(
+                text,
+                ui.at(ui::BOTTOM, vec2(0.0, y)),
+                ui::Align::Center,
+                ui.size.x - 10.0,
+                &ui.small,
+            );
+        };
+
+        ui.text(
+            "BIKE",
+            ui.at(ui::TOP, vec2(0.0, 55.0)),
+            ui::Align::Center,
+            &ui.big,
+        );
+        let text = match self.mode {
+            Mode::TimeTrial => "< TIME TRIAL >",
+            Mode::ScoreAttack => "< SCORE ATTACK >",
+            Mode::Speedrun => "< SPEEDRUN >",
+            Mode::Versus => "< VERSUS >",
+            Mode::VersusSharedStars => "< VERSUS: SHARED STARS >",
+            Mode::Tas => "< TOOL-ASSISTED >",
+        };
+        ui.text(
+            text,
+            ui.at(ui::TOP, vec2(0.0, 71.0)),
+            ui::Align::Center,
+            &ui.small,
+        );
+
+        let list = ui::List::new(LEVELS.len(), self.level_index, 95.0, ui.size.y - 55.0, 24.0);
+        for i in list.rows.clone() {
+            let y = list.y(i);
+            let at = |dx: f32| vec2(x + dx, y);
+            // cursor
+            if i == self.level_index && self.mode != Mode::Speedrun {
+                let c = Color::new(0.8, 0.8, 0.3, 0.3);
+                draw_rectangle(x - 130.0, y - 19.0, 280.0, 24.0, c);
+            }
+            let tp = if self.unlocked(i) {
+                ui.menu.clone()
+            } else {
+                TextParams {
+                    color: Color::new(1.0, 1.0, 1.0, 0.3),
+                    ..ui.menu.clone()
+                }
+            };
+            let left = ui::Align::Left;
+            ui.text("LEVEL", at(-120.0), left, &tp);
+            ui.mono(&(i + 1).to_string(), at(-50.0), ui::Align::Right, &tp);
+            if !self.unlocked(i) {
+                ui.text("LOCKED", at(48.0), left, &tp);
+                continue;
+            }
+            match self.mode {
+                Mode::TimeTrial => {
+                    ui.mono(&self.level_times[i].to_string(), at(48.0), left, &tp);
+                }
+                Mode::ScoreAttack => {
+                    let s = self.level_scores[i].to_string();
+                    ui.mono(&s, at(120.0), ui::Align::Right, &tp);
+                }
+                Mode::Speedrun => {
+                    ui.mono(&self.splits.0[i].pb.to_string(), at(48.0), left, &tp);
+                }
+                Mode::Versus | Mode::VersusSharedStars | Mode::Tas => {}
+            }
+            if let Some(medal) = self.medal(i) {
+                draw_circle(x + 138.0, y - 7.0, 5.0, medal.color());
+            }
+        }
+        list.draw_arrows(x - 140.0, Color::new(1.0, 1.0, 1.0, 0.5));
+
+        // leaderboard of the selected level, in the space right of the list
+        let key = (self.level_hashes[self.level_index], self.profile_hash());
+        let top = self.leaderboard.as_ref().and_then(|lb| lb.top.get(&key));
+        if let Some(top) = top {
+            let x = x + 155.0;
+            let width = ui.size.x - x - 5.0;
+            ui.text("TOP", vec2(x, 87.0), ui::Align::Left, &ui.small);
+            for (i, entry) in top.iter().enumerate() {
+                let y = 101.0 + i as f32 * 24.0;
+                let time = format!("{} {}", i + 1, entry.time);
+                ui.mono(&time, vec2(x, y), ui::Align::Left, &ui.small);
+                let tp = TextParams {
+                    color: Color::new(1.0, 1.0, 1.0, 0.6),
+                    ..ui.small.clone()
+                };
+                let player = entry.player.to_uppercase();
+                ui.text_fit(&player, vec2(x, y + 10.0), ui::Align::Left, width, &tp);
+            }
+        }
+
+        let title = &self.level_titles[self.level_index];
+        if self.mode != Mode::Speedrun && !title.is_empty() {
+            footer(title, -39.0);
+        }
+
+        footer(
+            &format!(
+                "[B] BIKE: {}",
+                self.profiles[self.profile].name.to_uppercase()
+            ),
+            -25.0,
+        );
+
+        let locked = (0..self.skins.len())
+            .filter(|&i| !self.skin_unlocked(i))
+            .count();
+        let mut text = format!("[S] SKIN: {}", self.skins[self.skin].name.to_uppercase());
+        if locked > 0 {
+            text += &format!(" ({} LOCKED)", locked);
+        }
+        footer(&text, -13.0);
+    }
+}
+
+fn window_conf() -> Conf {
+    Conf {
+        window_title: "Bike".to_string(),
+        high_dpi: true,
+        ..Default::default()
     }
 }
 
-#[macroquad::main("Bike")]
+#[macroquad::main(window_conf)]
 async fn main() {
     let mut game = Game::new().await;
     while game.running {
         game.update().await;
         game.draw();
+        game.screenshot();
         next_frame().await
     }
 }
@@ -1,22 +1,28 @@
-use macroquad::prelude::*;
-
-pub struct Materials {
-    pub font: Font,
-    pub wall_material: Material,
-    pub lava_material: Material,
-}
+// Fonts and the shaders levels are drawn with.
+//
+// The colors and shaders of a level come from a theme: one of the presets
+// below, picked with the "theme" property, with single colors and shaders
+// overridden by more properties. A level pack can have its own theme in
+// assets/theme.json, a JSON object with the same keys, which fills in
+// whatever a level leaves unset.
+//
+// Custom fragment shaders get the level position of the fragment in the
+// varying "uv" and these uniforms:
+//
+//     float time;        seconds since the level started
+//     vec2 camera;       level position at the center of the view
+//     vec4 color;        wall_color or lava_color
+//     vec4 line_color;   wall_line_color or lava_line_color
+//
+// Shaders that fail to load or compile are reported and replaced by the
+// default ones.
 
-impl Materials {
-    pub fn load() -> Materials {
+use macroquad::prelude::*;
 
-        let mut font = load_ttf_font_from_bytes(include_bytes!("../assets/Copilme.ttf")).unwrap();
-        font.set_filter(FilterMode::Linear);
+use crate::level::invalid_data;
+use crate::properties::{parse_color, Properties, Property};
 
-        Materials {
-            font,
-            wall_material: load_material(
-                ShaderSource::Glsl {
-                    vertex: "#version 100
+const VERTEX_SHADER: &str = "#version 100
 precision lowp float;
 attribute vec3 position;
 varying vec2 uv;
@@ -25,51 +31,267 @@ uniform mat4 Projection;
 void main() {
     gl_Position = Projection * Model * vec4(position, 1);
     uv = position.xy;
-}",
-                    fragment: "#version 100
+}";
+
+const WALL_SHADER: &str = "#version 100
 precision lowp float;
 varying vec2 uv;
+uniform vec4 color;
+uniform vec4 line_color;
 void main() {
     vec2 f = fract(uv / 32.0);
     vec2 a = abs(f * 2.0 - vec2(1.0));
     float x = pow(max(a.x, a.y), 20.0);
-    gl_FragColor = mix(
-        vec4(0.11, 0.34, 0.22, 1.0),
-        vec4(0.11, 0.4, 0.3, 1.0),
-        x);
-}"
-                },
-                Default::default(),
-            )
-            .unwrap(),
-            lava_material: load_material(
-                ShaderSource::Glsl {
-                    vertex: "#version 100
-precision lowp float;
-attribute vec3 position;
-varying vec2 uv;
-uniform mat4 Model;
-uniform mat4 Projection;
-void main() {
-    gl_Position = Projection * Model * vec4(position, 1);
-    uv = position.xy;
-}",
-                    fragment: "#version 100
+    gl_FragColor = mix(color, line_color, x);
+}";
+
+// like the walls, with a slow glow rolling through
+const LAVA_SHADER: &str = "#version 100
 precision lowp float;
 varying vec2 uv;
+uniform float time;
+uniform vec4 color;
+uniform vec4 line_color;
 void main() {
     vec2 f = fract(uv / 32.0);
     vec2 a = abs(f * 2.0 - vec2(1.0));
     float x = pow(max(a.x, a.y), 20.0);
-    gl_FragColor = mix(
-        vec4(0.4, 0.2, 0.2, 1.0),
-        vec4(0.5, 0.2, 0.2, 1.0),
-        x);
-}"
-                },
-                Default::default(),
-            )
-            .unwrap(),
+    float glow = 0.5 + 0.5 * sin(time * 2.0 + (uv.x + uv.y) / 24.0);
+    gl_FragColor = mix(color, line_color, max(x, glow * 0.3));
+}";
+
+// Colors and fragment shaders, unset ones are taken from the theme below.
+#[derive(Clone, Default, Debug, PartialEq)]
+pub struct Theme {
+    pub wall_color: Option<Color>,
+    pub wall_line_color: Option<Color>,
+    pub lava_color: Option<Color>,
+    pub lava_line_color: Option<Color>,
+    pub background: Option<Color>,
+    // paths of fragment shader files
+    pub wall_shader: Option<String>,
+    pub lava_shader: Option<String>,
+}
+
+impl Theme {
+    pub fn preset(name: &str) -> Option<Theme> {
+        let rgb = |r, g, b| Some(Color::new(r, g, b, 1.0));
+        let (wall, wall_line, lava, lava_line, background) = match name {
+            "default" => (
+                rgb(0.11, 0.34, 0.22),
+                rgb(0.11, 0.4, 0.3),
+                rgb(0.4, 0.2, 0.2),
+                rgb(0.5, 0.2, 0.2),
+                Color::from_rgba(10, 12, 15, 255),
+            ),
+            "night" => (
+                rgb(0.1, 0.12, 0.25),
+                rgb(0.16, 0.2, 0.38),
+                rgb(0.42, 0.14, 0.36),
+                rgb(0.6, 0.2, 0.5),
+                Color::from_rgba(3, 3, 10, 255),
+            ),
+            "desert" => (
+                rgb(0.55, 0.4, 0.22),
+                rgb(0.63, 0.47, 0.27),
+                rgb(0.75, 0.28, 0.08),
+                rgb(0.92, 0.42, 0.1),
+                Color::from_rgba(40, 26, 20, 255),
+            ),
+            "ice" => (
+                rgb(0.5, 0.66, 0.78),
+                rgb(0.68, 0.84, 0.94),
+                rgb(0.16, 0.26, 0.62),
+                rgb(0.26, 0.42, 0.88),
+                Color::from_rgba(12, 18, 28, 255),
+            ),
+            _ => return None,
+        };
+        Some(Theme {
+            wall_color: wall,
+            wall_line_color: wall_line,
+            lava_color: lava,
+            lava_line_color: lava_line,
+            background: Some(background),
+            wall_shader: None,
+            lava_shader: None,
+        })
+    }
+
+    // from map properties, the preset named by "theme" fills in the rest
+    pub fn parse(properties: &Properties) -> Result<Theme, std::io::Error> {
+        let theme = Theme {
+            wall_color: properties.color("wall_color"),
+            wall_line_color: properties.color("wall_line_color"),
+            lava_color: properties.color("lava_color"),
+            lava_line_color: properties.color("lava_line_color"),
+            background: properties.color("background"),
+            wall_shader: properties.str("wall_shader").map(|s| s.to_string()),
+            lava_shader: properties.str("lava_shader").map(|s| s.to_string()),
+        };
+        Ok(match properties.str("theme") {
+            Some(name) => {
+                let preset = Theme::preset(name)
+                    .ok_or_else(|| invalid_data(&format!("unknown theme {}", name)))?;
+                theme.or(&preset)
+            }
+            None => theme,
+        })
+    }
+
+    // a theme file of a level pack, colors are strings like "#RRGGBB"
+    pub fn parse_json(string: &str) -> Result<Theme, std::io::Error> {
+        let json: serde_json::Value = serde_json::from_str(string)?;
+        let object = json
+            .as_object()
+            .ok_or_else(|| invalid_data("theme is not an object"))?;
+        let mut properties = Properties::default();
+        for (name, value) in object {
+            let s = value
+                .as_str()
+                .ok_or_else(|| invalid_data(&format!("{} is not a string", name)))?;
+            let property = match parse_color(s) {
+                Some(c) => Property::Color(c),
+                None => Property::String(s.to_string()),
+            };
+            properties.set(name, property);
+        }
+        Theme::parse(&properties)
+    }
+
+    // the settings of self, with the unset ones from other
+    pub fn or(self, other: &Theme) -> Theme {
+        Theme {
+            wall_color: self.wall_color.or(other.wall_color),
+            wall_line_color: self.wall_line_color.or(other.wall_line_color),
+            lava_color: self.lava_color.or(other.lava_color),
+            lava_line_color: self.lava_line_color.or(other.lava_line_color),
+            background: self.background.or(other.background),
+            wall_shader: self.wall_shader.or(other.wall_shader.clone()),
+            lava_shader: self.lava_shader.or(other.lava_shader.clone()),
+        }
+    }
+
+    // makes shader paths relative to the given directory
+    pub fn resolve_paths(&mut self, dir: &str) {
+        for path in [&mut self.wall_shader, &mut self.lava_shader]
+            .into_iter()
+            .flatten()
+        {
+            if !dir.is_empty() && !path.starts_with('/') {
+                *path = format!("{}/{}", dir, path);
+            }
+        }
+    }
+}
+
+fn load_shader(fragment: &str) -> Result<Material, String> {
+    load_material(
+        ShaderSource::Glsl {
+            vertex: VERTEX_SHADER,
+            fragment,
+        },
+        MaterialParams {
+            uniforms: vec![
+                UniformDesc::new("time", UniformType::Float1),
+                UniformDesc::new("camera", UniformType::Float2),
+                UniformDesc::new("color", UniformType::Float4),
+                UniformDesc::new("line_color", UniformType::Float4),
+            ],
+            ..Default::default()
+        },
+    )
+    .map_err(|e| format!("{:?}", e))
+}
+
+pub struct Materials {
+    pub font: Font,
+    pub wall_material: Material,
+    pub lava_material: Material,
+    // the theme in use, with every color set
+    pub theme: Theme,
+    // custom wall and lava shaders in use and their compilation errors
+    sources: [Option<String>; 2],
+    shader_errors: Vec<String>,
+}
+
+impl Materials {
+    pub fn load() -> Materials {
+        let mut font = load_ttf_font_from_bytes(include_bytes!("../assets/Copilme.ttf")).unwrap();
+        font.set_filter(FilterMode::Linear);
+
+        let mut materials = Materials {
+            font,
+            wall_material: load_shader(WALL_SHADER).unwrap(),
+            lava_material: load_shader(LAVA_SHADER).unwrap(),
+            theme: Theme::default(),
+            sources: [None, None],
+            shader_errors: vec![],
+        };
+        materials.set_colors(Theme::preset("default").unwrap());
+        materials
+    }
+
+    fn set_colors(&mut self, theme: Theme) {
+        let theme = theme.or(&Theme::preset("default").unwrap());
+        let vec = |c: Option<Color>| c.unwrap().to_vec();
+        self.wall_material
+            .set_uniform("color", vec(theme.wall_color));
+        self.wall_material
+            .set_uniform("line_color", vec(theme.wall_line_color));
+        self.lava_material
+            .set_uniform("color", vec(theme.lava_color));
+        self.lava_material
+            .set_uniform("line_color", vec(theme.lava_line_color));
+        self.theme = theme;
+    }
+
+    // Switches to the given theme, with the defaults for whatever it leaves unset.
+    // Shaders that can't be used are reported, and the default ones are used instead.
+    pub async fn set_theme(&mut self, theme: Theme) -> Result<(), String> {
+        let mut errors = vec![];
+        let mut sources = [None, None];
+        let paths = [&theme.wall_shader, &theme.lava_shader];
+        for (source, path) in sources.iter_mut().zip(paths) {
+            if let Some(path) = path {
+                match load_string(path).await {
+                    Ok(s) => *source = Some(s),
+                    Err(e) => errors.push(format!("{}: {}", path, e)),
+                }
+            }
+        }
+        // compiling is slow, so only when the shaders changed
+        if sources != self.sources {
+            self.shader_errors.clear();
+            let defaults = [WALL_SHADER, LAVA_SHADER];
+            let mut materials = vec![];
+            for ((source, path), default) in sources.iter().zip(paths).zip(defaults) {
+                let custom = source.as_ref().map(|s| {
+                    load_shader(s).map_err(|e| format!("{}: {}", path.as_ref().unwrap(), e))
+                });
+                materials.push(match custom {
+                    Some(Ok(material)) => material,
+                    Some(Err(e)) => {
+                        self.shader_errors.push(e);
+                        load_shader(default).unwrap()
+                    }
+                    None => load_shader(default).unwrap(),
+                });
+            }
+            self.lava_material = materials.pop().unwrap();
+            self.wall_material = materials.pop().unwrap();
+            self.sources = sources;
+        }
+        errors.extend(self.shader_errors.iter().cloned());
+        self.set_colors(theme);
+        errors.into_iter().next().map_or(Ok(()), Err)
+    }
+
+    // per frame uniforms, before drawing the level
+    pub fn set_uniforms(&self, time: f32, camera: Vec2) {
+        for m in [&self.wall_material, &self.lava_material] {
+            m.set_uniform("time", time);
+            m.set_uniform("camera", camera);
         }
     }
 }
@@ -0,0 +1,59 @@
+use macroquad::prelude::*;
+
+use crate::LevelTime;
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub enum Medal {
+    Bronze,
+    Silver,
+    Gold,
+    Author,
+}
+
+impl Medal {
+    pub const ALL: [Medal; 4] = [Medal::Bronze, Medal::Silver, Medal::Gold, Medal::Author];
+
+    pub fn from_name(name: &str) -> Option<Medal> {
+        Medal::ALL
+            .into_iter()
+            .find(|m| m.to_string().eq_ignore_ascii_case(name))
+    }
+
+    pub fn color(self) -> Color {
+        match self {
+            Medal::Bronze => Color::from_rgba(170, 110, 60, 255),
+            Medal::Silver => Color::from_rgba(170, 175, 180, 255),
+            Medal::Gold => Color::from_rgba(220, 180, 40, 255),
+            Medal::Author => Color::from_rgba(60, 170, 110, 255),
+        }
+    }
+}
+
+impl std::fmt::Display for Medal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Medal::Bronze => write!(f, "BRONZE"),
+            Medal::Silver => write!(f, "SILVER"),
+            Medal::Gold => write!(f, "GOLD"),
+            Medal::Author => write!(f, "AUTHOR"),
+        }
+    }
+}
+
+// target times indexed by medal, from bronze to author
+#[derive(Clone, Copy)]
+pub struct ParTimes(pub [LevelTime; 4]);
+
+impl ParTimes {
+    pub fn from_secs(secs: [f32; 4]) -> Self {
+        Self(secs.map(LevelTime::new))
+    }
+
+    pub fn get(&self, medal: Medal) -> LevelTime {
+        self.0[medal as usize]
+    }
+
+    pub fn medal(&self, t: LevelTime) -> Option<Medal> {
+        Medal::ALL.into_iter().rev().find(|&m| t <= self.get(m))
+    }
+}
@@ -0,0 +1,280 @@
+// Visual feedback: dirt thrown up by spinning wheels, sparks on hard
+// landings, bursts from picked up stars, bubbles on lava and the rider's
+// debris on death.
+//
+// The game hands over the bikes and levels once per frame and the particles
+// work out what happened since the frame before, so they never touch the
+// simulation and runs play back the same with or without them. Particles
+// come from a pool of fixed size, the oldest make room when it's full, and
+// all of them are drawn as small squares into the frame's batch.
+
+use macroquad::prelude::*;
+use std::f32::consts::PI;
+
+use crate::bike::{Bike, HEAD_R};
+use crate::fx;
+use crate::level::{CollisionResult, Level, PolygonType};
+use crate::materials::Theme;
+
+const MAX_PARTICLES: usize = 1024;
+const GRAVITY: f32 = 100.0;
+
+// wheel surface speed where dirt starts flying, and dirt per unit of speed
+const DIRT_SPEED: f32 = 60.0;
+const DIRT_RATE: f32 = 0.4;
+// speed into the ground that makes sparks
+const SPARK_SPEED: f32 = 80.0;
+const BUBBLES_PER_SECOND: f32 = 10.0;
+
+const SPARK_COLOR: Color = Color::new(1.0, 0.85, 0.4, 1.0);
+const STAR_COLOR: Color = Color::new(0.8, 0.8, 0.3, 1.0);
+const RIDER_COLOR: Color = Color::new(0.51, 0.51, 0.51, 1.0);
+const FRAME_COLOR: Color = Color::new(0.27, 0.24, 0.2, 1.0);
+
+struct Particle {
+    pos: Vec2,
+    vel: Vec2,
+    age: f32,
+    life: f32,
+    size: f32,
+    color: Color,
+    // share of the gravity that pulls on it
+    gravity: f32,
+}
+
+// what a bike looked like in the last frame
+#[derive(Default)]
+struct Seen {
+    alive: bool,
+    contacts: [bool; 2],
+    wheel_vel: [Vec2; 2],
+    stars: Vec<bool>,
+    // dirt still to come from each wheel
+    dirt: [f32; 2],
+}
+
+pub struct Particles {
+    pool: Vec<Particle>,
+    // the slot to reuse when the pool is full
+    oldest: usize,
+    seen: Vec<Seen>,
+    rng: u32,
+    // bubbles still to come
+    bubbles: f32,
+}
+
+impl Default for Particles {
+    fn default() -> Self {
+        Particles {
+            pool: Vec::with_capacity(MAX_PARTICLES),
+            oldest: 0,
+            seen: vec![],
+            rng: 0x9e3779b9,
+            bubbles: 0.0,
+        }
+    }
+}
+
+impl Particles {
+    // xorshift, particles don't need more
+    fn random(&mut self, min: f32, max: f32) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        min + (self.rng as f32 / u32::MAX as f32) * (max - min)
+    }
+
+    fn random_dir(&mut self) -> Vec2 {
+        Vec2::from_angle(self.random(0.0, 2.0 * PI))
+    }
+
+    fn emit(&mut self, pos: Vec2, vel: Vec2, life: f32, size: f32, color: Color, gravity: f32) {
+        let p = Particle {
+            pos,
+            vel,
+            age: 0.0,
+            life,
+            size,
+            color,
+            gravity,
+        };
+        if self.pool.len() < MAX_PARTICLES {
+            self.pool.push(p);
+        } else {
+            self.pool[self.oldest] = p;
+            self.oldest = (self.oldest + 1) % MAX_PARTICLES;
+        }
+    }
+
+    fn burst(&mut self, pos: Vec2, n: usize, speed: f32, color: Color, gravity: f32) {
+        for _ in 0..n {
+            let vel = self.random_dir() * self.random(speed * 0.3, speed);
+            let life = self.random(0.3, 0.7);
+            let size = self.random(1.0, 2.0);
+            self.emit(pos, vel, life, size, color, gravity);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.pool.clear();
+        self.seen.clear();
+    }
+
+    // Moves the particles and starts new ones for what the bikes did since the
+    // last frame. view_size is the area of the level shown around each bike.
+    pub fn update(&mut self, dt: f32, views: &[(&Bike, &Level)], view_size: Vec2, theme: &Theme) {
+        self.seen.resize_with(views.len(), Default::default);
+        for (i, (bike, level)) in views.iter().enumerate() {
+            self.watch(i, dt, bike, level, theme);
+            self.lava_bubbles(
+                dt / views.len() as f32,
+                bike.frame.pos,
+                view_size,
+                level,
+                theme,
+            );
+        }
+
+        for p in self.pool.iter_mut() {
+            p.age += dt;
+            p.vel.y += GRAVITY * p.gravity * dt;
+            p.pos += p.vel * dt;
+        }
+        let before = self.pool.len();
+        self.pool.retain(|p| p.age < p.life);
+        if self.pool.len() < before {
+            self.oldest = 0;
+        }
+    }
+
+    fn watch(&mut self, i: usize, dt: f32, bike: &Bike, level: &Level, theme: &Theme) {
+        let r = bike.params.wheel_r;
+        let dirt_color = theme.wall_line_color.unwrap_or(FRAME_COLOR);
+        for (w, wheel) in bike.wheels.iter().enumerate() {
+            let Some(ci) = bike.contacts[w] else {
+                self.seen[i].dirt[w] = 0.0;
+                continue;
+            };
+            let ground = wheel.pos - ci.normal * r;
+
+            // sparks when hitting the ground hard
+            let impact = -self.seen[i].wheel_vel[w].dot(ci.normal);
+            if !self.seen[i].contacts[w] && impact > SPARK_SPEED {
+                let n = ((impact - SPARK_SPEED) / 10.0).min(20.0) as usize + 3;
+                for _ in 0..n {
+                    let spread = self.random(-1.2, 1.2);
+                    let vel = Vec2::from_angle(spread).rotate(ci.normal) * self.random(30.0, 90.0);
+                    let life = self.random(0.2, 0.5);
+                    self.emit(ground, vel, life, 1.0, SPARK_COLOR, 0.5);
+                }
+            }
+
+            // dirt thrown back by the spinning wheel
+            let speed = (wheel.ang_vel * r).abs();
+            if speed > DIRT_SPEED {
+                let dirt = &mut self.seen[i].dirt[w];
+                *dirt += (speed - DIRT_SPEED) * DIRT_RATE * dt;
+                let n = *dirt as usize;
+                *dirt -= n as f32;
+                let back = -ci.normal.perp() * wheel.ang_vel.signum();
+                for _ in 0..n {
+                    let vel =
+                        back * self.random(0.2, 0.5) * speed + ci.normal * self.random(20.0, 60.0);
+                    let life = self.random(0.3, 0.8);
+                    let size = self.random(1.0, 2.5);
+                    self.emit(ground, vel, life, size, dirt_color, 1.0);
+                }
+            }
+        }
+
+        // picked up stars
+        let stars = level.star_state();
+        if self.seen[i].stars.len() == stars.len() {
+            for (j, star) in level.stars.iter().enumerate() {
+                if self.seen[i].stars[j] && !stars[j] {
+                    self.burst(star.pos, 16, 60.0, STAR_COLOR, 0.0);
+                }
+            }
+        }
+
+        // the rider falls apart
+        if self.seen[i].alive && !bike.alive {
+            let head = bike.head();
+            for k in 0..40 {
+                let (pos, color) = match k % 3 {
+                    0 => (head + self.random_dir() * HEAD_R, RIDER_COLOR),
+                    1 => (bike.frame.pos, RIDER_COLOR),
+                    _ => (bike.frame.pos, FRAME_COLOR),
+                };
+                let vel = bike.frame.vel * 0.5 + self.random_dir() * self.random(40.0, 160.0);
+                let life = self.random(1.0, 2.0);
+                let size = self.random(2.0, 3.5);
+                self.emit(pos, vel, life, size, color, 1.0);
+            }
+            self.burst(head, 12, 100.0, SPARK_COLOR, 0.5);
+        }
+
+        let seen = &mut self.seen[i];
+        seen.alive = bike.alive;
+        seen.contacts = bike.contacts.map(|c| c.is_some());
+        seen.wheel_vel = [bike.wheels[0].vel, bike.wheels[1].vel];
+        seen.stars = stars;
+    }
+
+    // bubbles rising from the top of lava polygons around pos
+    fn lava_bubbles(&mut self, dt: f32, pos: Vec2, view_size: Vec2, level: &Level, theme: &Theme) {
+        self.bubbles += BUBBLES_PER_SECOND * dt;
+        if self.bubbles < 1.0 {
+            return;
+        }
+        let view = Rect::new(
+            pos.x - view_size.x * 0.5,
+            pos.y - view_size.y * 0.5,
+            view_size.x,
+            view_size.y,
+        );
+        let mut edges = vec![];
+        let mut total = 0.0;
+        for poly in level.polygons.iter().filter(|p| p.tpe == PolygonType::Lava) {
+            for (j, a) in poly.points.iter().enumerate() {
+                let b = poly.points[(j + 1) % poly.points.len()];
+                if view.contains((*a + b) * 0.5) {
+                    total += a.distance(b);
+                    edges.push((*a, b, total));
+                }
+            }
+        }
+        let color = theme.lava_line_color.unwrap_or(SPARK_COLOR);
+        while self.bubbles >= 1.0 {
+            self.bubbles -= 1.0;
+            if edges.is_empty() {
+                continue;
+            }
+            // any point of the visible edges, the ones with air above become bubbles
+            let d = self.random(0.0, total);
+            let (a, b, _) = edges.iter().find(|e| e.2 >= d).unwrap_or(&edges[0]);
+            let p = a.lerp(*b, self.random(0.0, 1.0)) - vec2(0.0, 2.0);
+            if level.circle_collision(p, 1.0) != CollisionResult::None {
+                continue;
+            }
+            let vel = vec2(self.random(-5.0, 5.0), -self.random(8.0, 20.0));
+            let life = self.random(0.5, 1.2);
+            let size = self.random(1.5, 3.0);
+            self.emit(p, vel, life, size, color, 0.0);
+        }
+    }
+
+    pub fn draw(&self, batch: &mut fx::Batch) {
+        for p in self.pool.iter() {
+            let t = p.age / p.life;
+            let mut color = p.color;
+            color.a *= (1.0 - t).min(0.3) / 0.3;
+            let h = p.size * (1.0 - t * 0.5) * 0.5;
+            let corners = [vec2(-h, -h), vec2(h, -h), vec2(h, h), vec2(-h, h)];
+            batch.push(
+                &corners.map(|c| fx::vert(p.pos + c, color)),
+                &[0, 1, 2, 0, 2, 3],
+            );
+        }
+    }
+}
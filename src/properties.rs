@@ -0,0 +1,143 @@
+// Tiled custom properties of maps, layers and objects.
+//
+// Tiled stores them as [{"name": ..., "type": ..., "value": ...}], the type
+// is one of string (the default), int, float, bool, color, file and object.
+// Colors are written as "#AARRGGBB" or "#RRGGBB", an empty color is unset.
+
+use macroquad::prelude::*;
+
+use crate::level::invalid_data;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Property {
+    Bool(bool),
+    Int(i64),
+    Float(f32),
+    String(String),
+    // a path relative to the file the property is in
+    File(String),
+    Color(Color),
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct Properties(Vec<(String, Property)>);
+
+pub fn parse_color(s: &str) -> Option<Color> {
+    let hex = s.strip_prefix('#')?;
+    let v = u32::from_str_radix(hex, 16).ok()?;
+    let (a, rgb) = match hex.len() {
+        6 => (255, v),
+        8 => (v >> 24, v & 0xffffff),
+        _ => return None,
+    };
+    Some(Color::from_rgba(
+        (rgb >> 16) as u8,
+        (rgb >> 8) as u8,
+        rgb as u8,
+        a as u8,
+    ))
+}
+
+fn color_string(c: Color) -> String {
+    let [r, g, b, a]: [u8; 4] = c.into();
+    format!("#{:02x}{:02x}{:02x}{:02x}", a, r, g, b)
+}
+
+impl Properties {
+    pub fn parse(json: &serde_json::Value) -> Result<Properties, std::io::Error> {
+        let mut properties = Properties::default();
+        for p in json["properties"].as_array().into_iter().flatten() {
+            let name = p["name"]
+                .as_str()
+                .ok_or_else(|| invalid_data("property without name"))?;
+            let value = &p["value"];
+            let invalid = || invalid_data(&format!("invalid value for property {}", name));
+            let property = match p["type"].as_str().unwrap_or("string") {
+                "bool" => Property::Bool(value.as_bool().ok_or_else(invalid)?),
+                "int" => Property::Int(value.as_i64().ok_or_else(invalid)?),
+                "float" => Property::Float(value.as_f64().ok_or_else(invalid)? as f32),
+                "color" => {
+                    let s = value.as_str().ok_or_else(invalid)?;
+                    if s.is_empty() {
+                        continue;
+                    }
+                    Property::Color(parse_color(s).ok_or_else(invalid)?)
+                }
+                "string" => Property::String(value.as_str().ok_or_else(invalid)?.into()),
+                "file" => Property::File(value.as_str().ok_or_else(invalid)?.into()),
+                // object references and custom classes
+                _ => continue,
+            };
+            properties.0.push((name.to_string(), property));
+        }
+        Ok(properties)
+    }
+
+    // back to Tiled's format
+    pub fn to_json(&self) -> serde_json::Value {
+        self.0
+            .iter()
+            .map(|(name, p)| {
+                let (tpe, value) = match p {
+                    Property::Bool(b) => ("bool", serde_json::json!(b)),
+                    Property::Int(i) => ("int", serde_json::json!(i)),
+                    Property::Float(f) => ("float", serde_json::json!(f)),
+                    Property::String(s) => ("string", serde_json::json!(s)),
+                    Property::File(s) => ("file", serde_json::json!(s)),
+                    Property::Color(c) => ("color", serde_json::json!(color_string(*c))),
+                };
+                serde_json::json!({ "name": name, "type": tpe, "value": value })
+            })
+            .collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Property)> {
+        self.0.iter().map(|(n, p)| (n.as_str(), p))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Property> {
+        self.0.iter().find(|(n, _)| n == name).map(|(_, p)| p)
+    }
+
+    pub fn set(&mut self, name: &str, property: Property) {
+        match self.0.iter_mut().find(|(n, _)| n == name) {
+            Some((_, p)) => *p = property,
+            None => self.0.push((name.to_string(), property)),
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.0.retain(|(n, _)| n != name);
+    }
+
+    // ints count as numbers too
+    pub fn f32(&self, name: &str) -> Option<f32> {
+        match self.get(name)? {
+            Property::Float(f) => Some(*f),
+            Property::Int(i) => Some(*i as f32),
+            _ => None,
+        }
+    }
+
+    // file properties are read as strings too
+    pub fn str(&self, name: &str) -> Option<&str> {
+        match self.get(name)? {
+            Property::String(s) | Property::File(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn bool(&self, name: &str) -> Option<bool> {
+        match self.get(name)? {
+            Property::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn color(&self, name: &str) -> Option<Color> {
+        match self.get(name)? {
+            Property::Color(c) => Some(*c),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,45 @@
+// Watches a file by polling its modification time.
+// Only native builds can look at files, elsewhere nothing ever changes.
+
+const POLL_INTERVAL: f32 = 0.25;
+
+pub struct Watcher {
+    pub path: String,
+    timer: f32,
+    #[cfg(not(target_arch = "wasm32"))]
+    modified: Option<std::time::SystemTime>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn modified(path: &str) -> Option<std::time::SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl Watcher {
+    pub fn new(path: &str) -> Self {
+        Watcher {
+            path: path.to_string(),
+            timer: 0.0,
+            #[cfg(not(target_arch = "wasm32"))]
+            modified: modified(path),
+        }
+    }
+
+    pub fn changed(&mut self, dt: f32) -> bool {
+        self.timer += dt;
+        if self.timer < POLL_INTERVAL {
+            return false;
+        }
+        self.timer = 0.0;
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let m = modified(&self.path);
+            if m != self.modified {
+                self.modified = m;
+                return true;
+            }
+        }
+        false
+    }
+}
@@ -0,0 +1,184 @@
+// Replays are stored as plain text, a header followed by run-length encoded
+// inputs, one line per run of identical physics ticks:
+//
+//     level <level file>
+//     profile <bike profile hash>
+//     skin <skin name>
+//     <ticks> <wheel> <jump> <toggle>
+//
+// wheel is '-', 'A' (accelerate) or 'B' (break), jump is '-', 'L' or 'R' and
+// toggle is '-' or 'T' (turn around). Each tick lasts PHYSICS_DT seconds.
+// The skin line is optional and only changes how the run looks.
+// Lines starting with '#' are ignored.
+
+use crate::bike::{Bike, BikeParams, Direction, Input, WheelInput, PHYSICS_DT};
+use crate::level::Level;
+use crate::LevelTime;
+
+pub const NO_INPUT: Input = Input {
+    toggle_dir: false,
+    wheel: WheelInput::None,
+    jump: None,
+};
+
+fn same(a: &Input, b: &Input) -> bool {
+    a.toggle_dir == b.toggle_dir && a.wheel == b.wheel && a.jump == b.jump
+}
+
+#[derive(Clone)]
+pub struct Replay {
+    pub level: String,
+    pub profile: u32,
+    // the skin the run was made with, empty for the default one
+    pub skin: String,
+    pub inputs: Vec<(u32, Input)>,
+}
+
+impl Replay {
+    pub fn new(level: &str, profile: u32) -> Self {
+        Replay {
+            level: level.to_string(),
+            profile,
+            skin: String::new(),
+            inputs: vec![],
+        }
+    }
+
+    // a run too long to add to starts another one
+    pub fn push(&mut self, input: &Input, ticks: u32) {
+        match self.inputs.last_mut() {
+            Some((n, last)) if same(last, input) && n.checked_add(ticks).is_some() => *n += ticks,
+            _ => self.inputs.push((ticks, *input)),
+        }
+    }
+
+    pub fn ticks(&self) -> u64 {
+        self.inputs.iter().map(|(n, _)| *n as u64).sum()
+    }
+
+    pub fn time(&self) -> LevelTime {
+        LevelTime::new(self.ticks() as f32 * PHYSICS_DT)
+    }
+
+    // the input of every tick
+    pub fn iter(&self) -> impl Iterator<Item = &Input> {
+        self.inputs
+            .iter()
+            .flat_map(|(n, input)| std::iter::repeat_n(input, *n as usize))
+    }
+
+    // keep only the first ticks
+    pub fn truncate(&mut self, ticks: u64) {
+        let mut left = ticks;
+        self.inputs.retain_mut(|(n, _)| {
+            *n = (*n as u64).min(left) as u32;
+            left -= *n as u64;
+            *n > 0
+        });
+    }
+
+    // Plays at most max_ticks of the replay from the start of the level.
+    // Returns the number of ticks it took to collect all stars, if it did.
+    pub fn run(&self, level: &mut Level, params: BikeParams, max_ticks: u64) -> Option<u64> {
+        level.restart();
+        let mut bike = Bike::new(level.start, params);
+        let max_ticks = usize::try_from(max_ticks).unwrap_or(usize::MAX);
+        for (tick, input) in self.iter().take(max_ticks).enumerate() {
+            bike.update(PHYSICS_DT, level, input);
+            if level.stars_left == 0 {
+                return Some(tick as u64 + 1);
+            }
+            if !bike.alive {
+                return None;
+            }
+        }
+        None
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        text.parse()
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        std::fs::write(path, self.to_string()).map_err(|e| e.to_string())
+    }
+}
+
+impl std::fmt::Display for Replay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "level {}", self.level)?;
+        writeln!(f, "profile {:08x}", self.profile)?;
+        if !self.skin.is_empty() {
+            writeln!(f, "skin {}", self.skin)?;
+        }
+        writeln!(f, "# time {}", self.time())?;
+        for (n, input) in self.inputs.iter() {
+            let wheel = match input.wheel {
+                WheelInput::None => '-',
+                WheelInput::Accelerate => 'A',
+                WheelInput::Break => 'B',
+            };
+            let jump = match input.jump {
+                None => '-',
+                Some(Direction::Left) => 'L',
+                Some(Direction::Right) => 'R',
+            };
+            let toggle = if input.toggle_dir { 'T' } else { '-' };
+            writeln!(f, "{} {} {} {}", n, wheel, jump, toggle)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Replay {
+    type Err = String;
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut replay = Replay::new("", 0);
+        // runs longer than this aren't worth playing back
+        let mut ticks: u32 = 0;
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = || format!("line {}: invalid replay line", i + 1);
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words[..] {
+                ["level", level] => replay.level = level.to_string(),
+                ["skin", skin] => replay.skin = skin.to_string(),
+                ["profile", hash] => {
+                    replay.profile = u32::from_str_radix(hash, 16).map_err(|_| err())?
+                }
+                [n, wheel, jump, toggle] => {
+                    let n = n.parse().map_err(|_| err())?;
+                    ticks = ticks
+                        .checked_add(n)
+                        .ok_or_else(|| format!("line {}: replay too long", i + 1))?;
+                    let input = Input {
+                        wheel: match wheel {
+                            "-" => WheelInput::None,
+                            "A" => WheelInput::Accelerate,
+                            "B" => WheelInput::Break,
+                            _ => return Err(err()),
+                        },
+                        jump: match jump {
+                            "-" => None,
+                            "L" => Some(Direction::Left),
+                            "R" => Some(Direction::Right),
+                            _ => return Err(err()),
+                        },
+                        toggle_dir: match toggle {
+                            "-" => false,
+                            "T" => true,
+                            _ => return Err(err()),
+                        },
+                    };
+                    replay.push(&input, n);
+                }
+                _ => return Err(err()),
+            }
+        }
+        Ok(replay)
+    }
+}
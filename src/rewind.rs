@@ -0,0 +1,53 @@
+// Keeps the last few seconds of a run so the player can scrub back after a crash.
+
+use std::collections::VecDeque;
+
+use crate::bike::Bike;
+use crate::level::Level;
+use crate::tricks::TrickDetector;
+
+// physics time between snapshots
+const INTERVAL: f32 = 0.05;
+// seconds that can be rewound
+const DURATION: f32 = 5.0;
+
+#[derive(Clone)]
+pub struct Snapshot {
+    pub time: f32,
+    pub bike: Bike,
+    pub stars: Vec<bool>,
+    pub tricks: TrickDetector,
+}
+
+#[derive(Default)]
+pub struct Rewind {
+    buffer: VecDeque<Snapshot>,
+    // rewound runs don't count for records
+    pub used: bool,
+}
+
+impl Rewind {
+    pub fn record(&mut self, time: f32, bike: &Bike, level: &Level, tricks: &TrickDetector) {
+        if self.buffer.back().is_some_and(|s| time - s.time < INTERVAL) {
+            return;
+        }
+        if self.buffer.len() >= (DURATION / INTERVAL) as usize {
+            self.buffer.pop_front();
+        }
+        self.buffer.push_back(Snapshot {
+            time,
+            bike: bike.clone(),
+            stars: level.star_state(),
+            tricks: tricks.clone(),
+        });
+    }
+
+    // step back to the most recent snapshot, the oldest one is kept
+    pub fn back(&mut self) -> Option<Snapshot> {
+        self.used |= !self.buffer.is_empty();
+        match self.buffer.len() {
+            0 | 1 => self.buffer.back().cloned(),
+            _ => self.buffer.pop_back(),
+        }
+    }
+}
@@ -0,0 +1,240 @@
+// Looks of the bike and rider.
+//
+// Skins are a JSON array in assets/skins.json, every field but "name" is
+// optional and defaults to the classic look:
+//
+//     {
+//       "name": "racer",
+//       "wheel_color": "#828282", "spring_color": "#8c5046",
+//       "frame_color": "#463c32", "rider_color": "#828282",
+//       "frame": [[2, -3], [9, -9], ...],
+//       "limbs": { "thigh": [7.0, 4.0], ... },
+//       "head_r": 4.5,
+//       "wheel_texture": "wheel.png",
+//       "frame_texture": "frame.png", "frame_texture_rect": [-17, -9, 31, 20],
+//       "unlock": { "medal": "gold", "levels": 3 }
+//     }
+//
+// The frame polygon is in bike coordinates: x points forward, y down, the
+// origin is the center of the frame. Limbs have a width at either end, they
+// are "body", "thigh", "shin", "foot", "upper_arm", "forearm" and "spring".
+// Textures are relative to the skins file and drawn instead of the frame
+// polygon and the wheel spokes. A skin with "unlock" can only be picked once
+// that many levels have the medal or a better one.
+
+use macroquad::prelude::*;
+
+use crate::level::invalid_data;
+use crate::medals::Medal;
+use crate::properties::parse_color;
+
+pub const LIMBS: [&str; 7] = [
+    "body",
+    "thigh",
+    "shin",
+    "foot",
+    "upper_arm",
+    "forearm",
+    "spring",
+];
+
+#[derive(Clone, Debug)]
+pub struct Skin {
+    pub name: String,
+    pub wheel_color: Color,
+    pub spring_color: Color,
+    pub frame_color: Color,
+    pub rider_color: Color,
+    pub frame: Vec<Vec2>,
+    // widths at both ends, in the order of LIMBS
+    pub limbs: [(f32, f32); 7],
+    pub head_r: f32,
+    pub wheel_texture: Option<String>,
+    pub frame_texture: Option<String>,
+    // where the frame texture goes in bike coordinates
+    pub frame_texture_rect: Rect,
+    // medal needed on a number of levels
+    pub unlock: Option<(Medal, usize)>,
+}
+
+impl Default for Skin {
+    fn default() -> Self {
+        let frame: Vec<Vec2> = [
+            (2, -3),
+            (9, -9),
+            (14, -4),
+            (1, 11),
+            (-1, 11),
+            (-11, 0),
+            (-17, -2),
+            (-17, -7),
+            (-10, -7),
+        ]
+        .iter()
+        .map(|&(x, y)| vec2(x as f32, y as f32))
+        .collect();
+        Skin {
+            name: "classic".to_string(),
+            wheel_color: Color::from_rgba(130, 130, 130, 255),
+            spring_color: Color::from_rgba(140, 80, 70, 255),
+            frame_color: Color::from_rgba(70, 60, 50, 255),
+            rider_color: Color::from_rgba(130, 130, 130, 255),
+            frame_texture_rect: bounds(&frame),
+            frame,
+            limbs: [
+                (6.5, 6.8),
+                (7.0, 4.0),
+                (4.0, 3.0),
+                (3.0, 2.0),
+                (4.0, 3.0),
+                (3.0, 2.5),
+                (3.0, 3.0),
+            ],
+            head_r: 4.5,
+            wheel_texture: None,
+            frame_texture: None,
+            unlock: None,
+        }
+    }
+}
+
+fn bounds(points: &[Vec2]) -> Rect {
+    let (min, max) = points.iter().fold(
+        (Vec2::splat(f32::MAX), Vec2::splat(f32::MIN)),
+        |(min, max), p| (min.min(*p), max.max(*p)),
+    );
+    Rect::new(min.x, min.y, max.x - min.x, max.y - min.y)
+}
+
+impl Skin {
+    pub fn limb(&self, name: &str) -> (f32, f32) {
+        let i = LIMBS.iter().position(|l| *l == name).unwrap();
+        self.limbs[i]
+    }
+
+    pub fn textures(&self) -> impl Iterator<Item = &str> {
+        [&self.wheel_texture, &self.frame_texture]
+            .into_iter()
+            .flatten()
+            .map(|s| s.as_str())
+    }
+
+    // whether enough levels have the medal, given the best medal of each level
+    pub fn unlocked(&self, medals: &[Option<Medal>]) -> bool {
+        match self.unlock {
+            Some((medal, levels)) => {
+                medals
+                    .iter()
+                    .filter(|m| m.is_some_and(|m| m >= medal))
+                    .count()
+                    >= levels
+            }
+            None => true,
+        }
+    }
+}
+
+// the skin with the given name, the first one if there's none
+pub fn find<'a>(skins: &'a [Skin], name: &str) -> &'a Skin {
+    skins.iter().find(|s| s.name == name).unwrap_or(&skins[0])
+}
+
+// Texture paths are made relative to dir.
+pub fn parse_skins(string: &str, dir: &str) -> Result<Vec<Skin>, std::io::Error> {
+    let json: serde_json::Value = serde_json::from_str(string)?;
+    let mut skins = vec![];
+    for s in json
+        .as_array()
+        .ok_or_else(|| invalid_data("expected an array of skins"))?
+    {
+        let o = s
+            .as_object()
+            .ok_or_else(|| invalid_data("expected a skin object"))?;
+        let name = o
+            .get("name")
+            .and_then(|n| n.as_str())
+            .filter(|n| !n.is_empty() && !n.contains(char::is_whitespace))
+            .ok_or_else(|| invalid_data("missing skin name or name with spaces"))?;
+        let err = |k: &str| invalid_data(&format!("{}: invalid {}", name, k));
+        let number =
+            |v: &serde_json::Value, k: &str| v.as_f64().map(|f| f as f32).ok_or_else(|| err(k));
+        let point = |v: &serde_json::Value, k: &str| match v.as_array().map(|a| &a[..]) {
+            Some([x, y]) => Ok(vec2(number(x, k)?, number(y, k)?)),
+            _ => Err(err(k)),
+        };
+        let path = |v: &serde_json::Value, k: &str| {
+            let p = v.as_str().ok_or_else(|| err(k))?;
+            Ok::<_, std::io::Error>(if dir.is_empty() || p.starts_with('/') {
+                p.to_string()
+            } else {
+                format!("{}/{}", dir, p)
+            })
+        };
+        let mut skin = Skin {
+            name: name.to_string(),
+            ..Default::default()
+        };
+        let mut texture_rect = None;
+        for (k, v) in o.iter().filter(|(k, _)| *k != "name") {
+            let color = || v.as_str().and_then(parse_color).ok_or_else(|| err(k));
+            match k.as_str() {
+                "wheel_color" => skin.wheel_color = color()?,
+                "spring_color" => skin.spring_color = color()?,
+                "frame_color" => skin.frame_color = color()?,
+                "rider_color" => skin.rider_color = color()?,
+                "frame" => {
+                    let points = v.as_array().ok_or_else(|| err(k))?;
+                    skin.frame = points
+                        .iter()
+                        .map(|p| point(p, k))
+                        .collect::<Result<_, _>>()?;
+                    if skin.frame.len() < 3 {
+                        return Err(err(k));
+                    }
+                }
+                "limbs" => {
+                    for (limb, widths) in v.as_object().ok_or_else(|| err(k))? {
+                        let i = LIMBS.iter().position(|l| l == limb).ok_or_else(|| {
+                            invalid_data(&format!("{}: unknown limb {}", name, limb))
+                        })?;
+                        let w = point(widths, limb)?;
+                        skin.limbs[i] = (w.x, w.y);
+                    }
+                }
+                "head_r" => skin.head_r = number(v, k)?,
+                "wheel_texture" => skin.wheel_texture = Some(path(v, k)?),
+                "frame_texture" => skin.frame_texture = Some(path(v, k)?),
+                "frame_texture_rect" => match v.as_array().map(|a| &a[..]) {
+                    Some([x, y, w, h]) => {
+                        let [x, y, w, h] = [x, y, w, h].map(|n| number(n, k));
+                        texture_rect = Some(Rect::new(x?, y?, w?, h?));
+                    }
+                    _ => return Err(err(k)),
+                },
+                "unlock" => {
+                    let medal = v["medal"]
+                        .as_str()
+                        .and_then(Medal::from_name)
+                        .ok_or_else(|| err(k))?;
+                    let levels = v["levels"].as_u64().unwrap_or(1) as usize;
+                    skin.unlock = Some((medal, levels));
+                }
+                _ => return Err(invalid_data(&format!("{}: unknown field {}", name, k))),
+            }
+        }
+        skin.frame_texture_rect = texture_rect.unwrap_or_else(|| bounds(&skin.frame));
+        skins.push(skin);
+    }
+    if skins.is_empty() {
+        return Err(invalid_data("no skins"));
+    }
+    Ok(skins)
+}
+
+pub async fn load_skins(path: &str) -> Result<Vec<Skin>, std::io::Error> {
+    let string = macroquad::file::load_string(path)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::NotFound, e.to_string()))?;
+    let dir = path.rsplit_once('/').map_or("", |(dir, _)| dir);
+    parse_skins(&string, dir)
+}
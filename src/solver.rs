@@ -0,0 +1,207 @@
+// Searches for a run that collects all stars of a level.
+//
+// Beam search over input sequences: every candidate run is extended by each
+// of a few fixed inputs held for one segment, the runs that got furthest
+// survive. Runs are ranked by collected stars, then by distance to the nearest
+// remaining star. Only the best run per cell of position, velocity and angle
+// is kept so the beam doesn't collapse into one spot, which helps with routes
+// that first have to lead away from a star.
+
+use macroquad::prelude::*;
+
+use crate::bike::{Bike, BikeParams, Direction, Input, WheelInput, PHYSICS_DT};
+use crate::level::Level;
+use crate::replay::Replay;
+
+const ACTIONS: [Input; 8] = [
+    action(WheelInput::Accelerate, None, false),
+    action(WheelInput::None, None, false),
+    action(WheelInput::Break, None, false),
+    action(WheelInput::Accelerate, Some(Direction::Left), false),
+    action(WheelInput::Accelerate, Some(Direction::Right), false),
+    action(WheelInput::None, Some(Direction::Left), false),
+    action(WheelInput::None, Some(Direction::Right), false),
+    action(WheelInput::Accelerate, None, true),
+];
+
+const fn action(wheel: WheelInput, jump: Option<Direction>, toggle_dir: bool) -> Input {
+    Input {
+        toggle_dir,
+        wheel,
+        jump,
+    }
+}
+
+// runs that are this close in position, velocity and angle count as the same
+const CELL_SIZE: f32 = 8.0;
+const CELL_VEL: f32 = 20.0;
+const CELL_ANG: f32 = std::f32::consts::PI / 8.0;
+const STAR_SCORE: f32 = 10000.0;
+
+pub struct Config {
+    pub beam_width: usize,
+    // physics ticks each input is held for
+    pub segment_ticks: u32,
+    // give up after this many seconds of level time
+    pub max_time: f32,
+    pub threads: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            beam_width: 64,
+            segment_ticks: 500,
+            max_time: 60.0,
+            threads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Node {
+    bike: Bike,
+    stars: Vec<bool>,
+    replay: Replay,
+    score: f32,
+}
+
+enum Outcome {
+    Alive(Box<Node>),
+    // ticks of the whole run
+    Finished(Replay, u64),
+}
+
+fn score(bike: &Bike, level: &Level) -> f32 {
+    let collected = level.stars.len() - level.stars_left;
+    let dist = level
+        .takeable_stars()
+        .map(|s| s.pos.distance(bike.frame.pos))
+        .fold(f32::MAX, f32::min);
+    collected as f32 * STAR_SCORE - dist.min(STAR_SCORE)
+}
+
+fn cell(node: &Node) -> (IVec2, IVec2, i32, usize, bool) {
+    let frame = &node.bike.frame;
+    (
+        (frame.pos / CELL_SIZE).floor().as_ivec2(),
+        (frame.vel / CELL_VEL).floor().as_ivec2(),
+        (frame.ang.rem_euclid(2.0 * std::f32::consts::PI) / CELL_ANG) as i32,
+        node.stars.iter().filter(|a| !**a).count(),
+        node.bike.dir == Direction::Right,
+    )
+}
+
+fn expand(node: &Node, level: &mut Level, segment_ticks: u32) -> Vec<Outcome> {
+    let mut outcomes = vec![];
+    for input in ACTIONS.iter() {
+        level.set_star_state(&node.stars);
+        let mut bike = node.bike.clone();
+        let mut replay = node.replay.clone();
+        let mut outcome = None;
+        for tick in 1..=segment_ticks {
+            bike.update(PHYSICS_DT, level, input);
+            if level.stars_left == 0 {
+                replay.push(input, tick);
+                let ticks = replay.ticks();
+                outcome = Some(Outcome::Finished(replay.clone(), ticks));
+                break;
+            }
+            if !bike.alive {
+                break;
+            }
+        }
+        if let Some(o) = outcome {
+            outcomes.push(o);
+        } else if bike.alive {
+            replay.push(input, segment_ticks);
+            outcomes.push(Outcome::Alive(Box::new(Node {
+                score: score(&bike, level),
+                stars: level.star_state(),
+                bike,
+                replay,
+            })));
+        }
+    }
+    outcomes
+}
+
+// Returns the fastest run found, progress is called with the level time
+// searched so far and the most stars collected.
+pub fn solve(
+    source: &[u8],
+    level_file: &str,
+    profile: BikeParams,
+    config: &Config,
+    mut progress: impl FnMut(f32, usize),
+) -> Result<Option<Replay>, std::io::Error> {
+    let mut levels = vec![];
+    for _ in 0..config.threads.max(1) {
+        levels.push(Level::parse_file(level_file, source)?);
+    }
+    let params = levels[0].bike_params(profile);
+    let start = Node {
+        bike: Bike::new(levels[0].start, params),
+        stars: levels[0].star_state(),
+        replay: Replay::new(level_file, profile.hash()),
+        score: 0.0,
+    };
+    let total_stars = start.stars.len();
+
+    let mut beam = vec![start];
+    let mut time = 0.0;
+    while time < config.max_time && !beam.is_empty() {
+        time += config.segment_ticks as f32 * PHYSICS_DT;
+
+        let chunk = beam.len().div_ceil(levels.len());
+        let outcomes: Vec<Outcome> = std::thread::scope(|s| {
+            let handles: Vec<_> = beam
+                .chunks(chunk)
+                .zip(levels.iter_mut())
+                .map(|(nodes, level)| {
+                    s.spawn(move || {
+                        nodes
+                            .iter()
+                            .flat_map(|n| expand(n, level, config.segment_ticks))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect()
+        });
+
+        let mut best: Option<(Replay, u64)> = None;
+        let mut nodes = vec![];
+        for o in outcomes {
+            match o {
+                Outcome::Finished(replay, ticks) => {
+                    if best.as_ref().is_none_or(|(_, t)| ticks < *t) {
+                        best = Some((replay, ticks));
+                    }
+                }
+                Outcome::Alive(node) => nodes.push(*node),
+            }
+        }
+        if let Some((replay, _)) = best {
+            progress(time, total_stars);
+            return Ok(Some(replay));
+        }
+
+        nodes.sort_by(|a, b| b.score.total_cmp(&a.score));
+        let mut cells = std::collections::HashSet::new();
+        nodes.retain(|n| cells.insert(cell(n)));
+        nodes.truncate(config.beam_width);
+        beam = nodes;
+
+        let collected = beam
+            .iter()
+            .map(|n| n.stars.iter().filter(|a| !**a).count())
+            .max()
+            .unwrap_or(0);
+        progress(time, collected);
+    }
+    Ok(None)
+}
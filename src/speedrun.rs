@@ -0,0 +1,166 @@
+// Splits are stored as plain text with one line per level, in level order:
+//
+//     <level file> <personal best split> <best segment>
+//
+// Times are written like the in-game clock (minutes:seconds:hundredths).
+// The personal best split is the run time at the end of that level,
+// the best segment is the fastest time ever spent on that level during a run.
+// Missing times are written as "--:--:--". Lines starting with '#' are ignored.
+//
+// Every bike profile keeps its own splits, in a file named after the profile hash.
+
+use crate::LevelTime;
+
+pub fn splits_file(profile: u32) -> String {
+    format!("splits-{:08x}.txt", profile)
+}
+
+#[derive(Clone)]
+pub struct Split {
+    pub level: String,
+    pub pb: LevelTime,
+    pub gold: LevelTime,
+}
+
+#[derive(Clone)]
+pub struct Splits(pub Vec<Split>);
+
+impl Splits {
+    pub fn new(levels: &[&str]) -> Self {
+        Self(
+            levels
+                .iter()
+                .map(|l| Split {
+                    level: l.to_string(),
+                    pb: LevelTime::invalid(),
+                    gold: LevelTime::invalid(),
+                })
+                .collect(),
+        )
+    }
+
+    // take over the times of the given splits for levels that are still around
+    fn merge(&mut self, other: &Splits) {
+        for split in self.0.iter_mut() {
+            if let Some(s) = other.0.iter().find(|s| s.level == split.level) {
+                split.pb = s.pb;
+                split.gold = s.gold;
+            }
+        }
+    }
+
+    // no file yet is no error, the splits just start empty
+    pub fn load(levels: &[&str], path: &str) -> Result<Self, String> {
+        let mut splits = Self::new(levels);
+        #[cfg(not(target_arch = "wasm32"))]
+        match std::fs::read_to_string(path) {
+            Ok(text) => splits.merge(&text.parse().map_err(|e| format!("{}: {}", path, e))?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("{}: {}", path, e)),
+        }
+        Ok(splits)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        #[cfg(not(target_arch = "wasm32"))]
+        std::fs::write(path, self.to_string()).map_err(|e| format!("{}: {}", path, e))?;
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Splits {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "# level pb_split best_segment")?;
+        for s in self.0.iter() {
+            writeln!(f, "{} {} {}", s.level, s.pb, s.gold)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Splits {
+    type Err = String;
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut splits = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = || format!("line {}: invalid split", i + 1);
+            let mut words = line.split_whitespace();
+            let level = words.next().ok_or_else(err)?.to_string();
+            let pb = words.next().ok_or_else(err)?.parse().map_err(|_| err())?;
+            let gold = words.next().ok_or_else(err)?.parse().map_err(|_| err())?;
+            splits.push(Split { level, pb, gold });
+        }
+        Ok(Splits(splits))
+    }
+}
+
+// signed difference between two times, e.g. "-00:01:20"
+pub fn format_delta(t: LevelTime, reference: LevelTime) -> String {
+    let d = t.0 as i64 - reference.0 as i64;
+    let sign = if d < 0 { '-' } else { '+' };
+    format!("{}{}", sign, LevelTime(d.unsigned_abs() as u32))
+}
+
+pub struct Speedrun {
+    // total physics time, including failed attempts
+    pub time: f64,
+    // run times at the end of each completed level
+    pub splits: Vec<LevelTime>,
+    // the level segments of this run that were faster than the best segment
+    pub golds: Vec<bool>,
+    pub pb: Splits,
+    pub new_pb: bool,
+    path: String,
+}
+
+impl Speedrun {
+    pub fn new(pb: Splits, path: String) -> Self {
+        Self {
+            time: 0.0,
+            splits: vec![],
+            golds: vec![],
+            pb,
+            new_pb: false,
+            path,
+        }
+    }
+
+    pub fn current(&self) -> LevelTime {
+        LevelTime::new(self.time as f32)
+    }
+
+    pub fn finished(&self) -> bool {
+        self.splits.len() == self.pb.0.len()
+    }
+
+    // finish the current level and store the updated splits
+    pub fn split(&mut self) -> Result<(), String> {
+        if self.finished() {
+            return Ok(());
+        }
+        let t = self.current();
+        let prev = self.splits.last().map_or(0, |t| t.0);
+        let segment = LevelTime(t.0 - prev);
+        let i = self.splits.len();
+        self.splits.push(t);
+
+        let gold = &mut self.pb.0[i].gold;
+        let is_gold = *gold != LevelTime::invalid() && segment < *gold;
+        self.golds.push(is_gold);
+        if segment < *gold {
+            *gold = segment;
+        }
+
+        if self.finished() && t < self.pb.0[i].pb {
+            self.new_pb = true;
+            for (s, t) in self.pb.0.iter_mut().zip(self.splits.iter()) {
+                s.pb = *t;
+            }
+        }
+        self.pb.save(&self.path)
+    }
+}
@@ -0,0 +1,516 @@
+// Reads level geometry from SVG files, for levels drawn in vector editors.
+//
+// Shapes inside groups (layers) with the id or Inkscape label "walls" or
+// "lava" become polygons: paths, polygons, polylines, rectangles, circles and
+// ellipses. Every subpath of a path is a polygon of its own, curves and arcs
+// are flattened into line segments. Circles with an id or label starting with
+// "start" or "star" place the start and the stars, anywhere in the document.
+// Transforms are applied, one SVG user unit is one level unit.
+//
+// Only the XML this needs is understood: elements and attributes, comments,
+// processing instructions and doctypes are skipped.
+
+use macroquad::math::Affine2;
+use macroquad::prelude::*;
+
+use crate::level::{invalid_data, Level, Polygon, PolygonType, Star};
+
+// how far flattened curves may stray from the real ones, in level units
+const CURVE_TOLERANCE: f32 = 0.5;
+const MAX_SUBDIVISIONS: u32 = 16;
+
+struct Tag<'a> {
+    name: &'a str,
+    attrs: Vec<(&'a str, &'a str)>,
+    closing: bool,
+    empty: bool,
+}
+
+impl<'a> Tag<'a> {
+    fn attr(&self, name: &str) -> Option<&'a str> {
+        self.attrs.iter().find(|(k, _)| *k == name).map(|(_, v)| *v)
+    }
+
+    fn num(&self, name: &str) -> f32 {
+        self.attr(name)
+            .and_then(|v| Numbers::new(v).next())
+            .unwrap_or(0.0)
+    }
+
+    // the label Inkscape shows, falling back to the id
+    fn label(&self) -> &'a str {
+        self.attr("inkscape:label")
+            .or(self.attr("id"))
+            .unwrap_or_default()
+    }
+}
+
+// splits a document into tags, text between tags is ignored
+fn tags(text: &str) -> Result<Vec<Tag<'_>>, std::io::Error> {
+    let mut tags = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+        let skip = [
+            ("<!--", "-->"),
+            ("<![CDATA[", "]]>"),
+            ("<?", "?>"),
+            ("<!", ">"),
+        ]
+        .iter()
+        .find(|(open, _)| rest.starts_with(open));
+        if let Some((_, close)) = skip {
+            let end = rest
+                .find(close)
+                .ok_or_else(|| invalid_data("unterminated tag"))?;
+            rest = &rest[end + close.len()..];
+            continue;
+        }
+
+        let end = tag_end(rest).ok_or_else(|| invalid_data("unterminated tag"))?;
+        let mut inner = &rest[1..end];
+        rest = &rest[end + 1..];
+        let closing = inner.starts_with('/');
+        let empty = inner.ends_with('/');
+        inner = inner.trim_start_matches('/').trim_end_matches('/');
+        let name_end = inner
+            .find(|c: char| c.is_whitespace())
+            .unwrap_or(inner.len());
+        let mut tag = Tag {
+            name: &inner[..name_end],
+            attrs: vec![],
+            closing,
+            empty,
+        };
+        let mut a = &inner[name_end..];
+        while let Some(eq) = a.find('=') {
+            let name = a[..eq].trim();
+            let value = a[eq + 1..].trim_start();
+            let quote = value
+                .chars()
+                .next()
+                .filter(|c| *c == '"' || *c == '\'')
+                .ok_or_else(|| invalid_data("unquoted attribute"))?;
+            let len = value[1..]
+                .find(quote)
+                .ok_or_else(|| invalid_data("unterminated attribute"))?;
+            tag.attrs.push((name, &value[1..1 + len]));
+            a = &value[len + 2..];
+        }
+        tags.push(tag);
+    }
+    Ok(tags)
+}
+
+// the closing '>' of a tag, skipping quoted attribute values
+fn tag_end(s: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            (None, '>') => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+// numbers in attributes and path data, separated by whitespace and commas or
+// not at all ("1-2", "1.5.5")
+struct Numbers<'a> {
+    s: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Numbers<'a> {
+    fn new(s: &'a str) -> Self {
+        Numbers {
+            s: s.as_bytes(),
+            pos: 0,
+        }
+    }
+
+    fn skip_separators(&mut self) {
+        while self
+            .s
+            .get(self.pos)
+            .is_some_and(|c| c.is_ascii_whitespace() || *c == b',')
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_separators();
+        self.s.get(self.pos).copied()
+    }
+
+    // arc flags may be written without separators ("a1 1 0 01 5 5")
+    fn flag(&mut self) -> Option<bool> {
+        let c = self.peek()?;
+        self.pos += 1;
+        match c {
+            b'0' => Some(false),
+            b'1' => Some(true),
+            _ => None,
+        }
+    }
+}
+
+impl Iterator for Numbers<'_> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        self.skip_separators();
+        let start = self.pos;
+        let digits = |n: &mut Self| {
+            while n.s.get(n.pos).is_some_and(|c| c.is_ascii_digit()) {
+                n.pos += 1;
+            }
+        };
+        if matches!(self.s.get(self.pos), Some(b'+' | b'-')) {
+            self.pos += 1;
+        }
+        digits(self);
+        if self.s.get(self.pos) == Some(&b'.') {
+            self.pos += 1;
+            digits(self);
+        }
+        if matches!(self.s.get(self.pos), Some(b'e' | b'E')) {
+            let mantissa = self.pos;
+            self.pos += 1;
+            if matches!(self.s.get(self.pos), Some(b'+' | b'-')) {
+                self.pos += 1;
+            }
+            let exponent = self.pos;
+            digits(self);
+            if self.pos == exponent {
+                self.pos = mantissa;
+            }
+        }
+        let n = std::str::from_utf8(&self.s[start..self.pos])
+            .ok()?
+            .parse()
+            .ok();
+        if n.is_none() {
+            self.pos = start;
+        }
+        n
+    }
+}
+
+fn parse_transform(s: &str) -> Affine2 {
+    let mut t = Affine2::IDENTITY;
+    for part in s.split(')') {
+        let Some((name, args)) = part.split_once('(') else {
+            continue;
+        };
+        let a: Vec<f32> = Numbers::new(args).collect();
+        let arg = |i: usize, default: f32| a.get(i).copied().unwrap_or(default);
+        let m = match name.trim_matches(|c: char| c.is_whitespace() || c == ',') {
+            "matrix" if a.len() == 6 => {
+                Affine2::from_cols_array(&[a[0], a[1], a[2], a[3], a[4], a[5]])
+            }
+            "translate" => Affine2::from_translation(vec2(arg(0, 0.0), arg(1, 0.0))),
+            "scale" => Affine2::from_scale(vec2(arg(0, 1.0), arg(1, arg(0, 1.0)))),
+            "rotate" => {
+                let c = vec2(arg(1, 0.0), arg(2, 0.0));
+                Affine2::from_translation(c)
+                    * Affine2::from_angle(arg(0, 0.0).to_radians())
+                    * Affine2::from_translation(-c)
+            }
+            "skewX" => {
+                Affine2::from_cols_array(&[1.0, 0.0, arg(0, 0.0).to_radians().tan(), 1.0, 0.0, 0.0])
+            }
+            "skewY" => {
+                Affine2::from_cols_array(&[1.0, arg(0, 0.0).to_radians().tan(), 0.0, 1.0, 0.0, 0.0])
+            }
+            _ => Affine2::IDENTITY,
+        };
+        t *= m;
+    }
+    t
+}
+
+fn flatten_cubic(out: &mut Vec<Vec2>, p: [Vec2; 4], depth: u32) {
+    let chord = p[3] - p[0];
+    let dist = |q: Vec2| {
+        if chord.length_squared() < 1e-12 {
+            q.distance(p[0])
+        } else {
+            chord.perp_dot(q - p[0]).abs() / chord.length()
+        }
+    };
+    if depth >= MAX_SUBDIVISIONS || dist(p[1]).max(dist(p[2])) <= CURVE_TOLERANCE {
+        out.push(p[3]);
+        return;
+    }
+    let ab = p[0].lerp(p[1], 0.5);
+    let bc = p[1].lerp(p[2], 0.5);
+    let cd = p[2].lerp(p[3], 0.5);
+    let abc = ab.lerp(bc, 0.5);
+    let bcd = bc.lerp(cd, 0.5);
+    let mid = abc.lerp(bcd, 0.5);
+    flatten_cubic(out, [p[0], ab, abc, mid], depth + 1);
+    flatten_cubic(out, [mid, bcd, cd, p[3]], depth + 1);
+}
+
+// segments needed for an arc of the given radius and angle
+fn arc_segments(r: f32, angle: f32) -> usize {
+    let step = 2.0 * (1.0 - CURVE_TOLERANCE / r.max(CURVE_TOLERANCE)).acos();
+    (angle.abs() / step.max(0.01)).ceil().max(1.0) as usize
+}
+
+// points on an ellipse from angle a0 by da, excluding the first
+fn flatten_ellipse(out: &mut Vec<Vec2>, c: Vec2, r: Vec2, rot: f32, a0: f32, da: f32) {
+    let n = arc_segments(r.x.max(r.y), da);
+    let rot = Vec2::from_angle(rot);
+    for i in 1..=n {
+        let a = a0 + da * i as f32 / n as f32;
+        out.push(c + rot.rotate(vec2(a.cos() * r.x, a.sin() * r.y)));
+    }
+}
+
+// SVG elliptical arc from the current point, converted to center form as in
+// the SVG implementation notes
+fn flatten_arc(
+    out: &mut Vec<Vec2>,
+    from: Vec2,
+    r: Vec2,
+    rot: f32,
+    large: bool,
+    sweep: bool,
+    to: Vec2,
+) {
+    let mut r = r.abs();
+    if r.x < 1e-6 || r.y < 1e-6 || from == to {
+        out.push(to);
+        return;
+    }
+    let rot = rot.to_radians();
+    let p = Vec2::from_angle(-rot).rotate((from - to) / 2.0);
+    let lambda = (p.x / r.x).powi(2) + (p.y / r.y).powi(2);
+    if lambda > 1.0 {
+        r *= lambda.sqrt();
+    }
+    let num = r.x * r.x * r.y * r.y - r.x * r.x * p.y * p.y - r.y * r.y * p.x * p.x;
+    let den = r.x * r.x * p.y * p.y + r.y * r.y * p.x * p.x;
+    let mut k = (num / den).max(0.0).sqrt();
+    if large == sweep {
+        k = -k;
+    }
+    let c = k * vec2(r.x * p.y / r.y, -r.y * p.x / r.x);
+    let center = Vec2::from_angle(rot).rotate(c) + (from + to) / 2.0;
+    let angle = |v: Vec2| v.y.atan2(v.x);
+    let u = (p - c) / r;
+    let v = (-p - c) / r;
+    let a0 = angle(u);
+    let mut da = angle(v) - a0;
+    if sweep && da < 0.0 {
+        da += std::f32::consts::TAU;
+    } else if !sweep && da > 0.0 {
+        da -= std::f32::consts::TAU;
+    }
+    flatten_ellipse(out, center, r, rot, a0, da);
+}
+
+// every subpath becomes a polygon
+fn parse_path(d: &str) -> Result<Vec<Vec<Vec2>>, std::io::Error> {
+    let invalid = || invalid_data("invalid path data");
+    let mut subpaths = vec![];
+    let mut points: Vec<Vec2> = vec![];
+    let mut n = Numbers::new(d);
+    let mut cmd = b'M';
+    let mut cur = Vec2::ZERO;
+    let mut start = Vec2::ZERO;
+    // reflected control points of the last curve, for S and T
+    let mut last_cubic = None;
+    let mut last_quad = None;
+    while let Some(c) = n.peek() {
+        if c.is_ascii_alphabetic() {
+            cmd = c;
+            n.pos += 1;
+        } else if cmd == b'Z' || cmd == b'z' {
+            return Err(invalid());
+        }
+        let rel = cmd.is_ascii_lowercase();
+        let origin = if rel { cur } else { Vec2::ZERO };
+        let mut num = || n.next().ok_or_else(invalid);
+        let (mut cubic, mut quad) = (None, None);
+        match cmd.to_ascii_uppercase() {
+            b'M' => {
+                if points.len() > 1 {
+                    subpaths.push(std::mem::take(&mut points));
+                }
+                points.clear();
+                cur = origin + vec2(num()?, num()?);
+                start = cur;
+                points.push(cur);
+                // further coordinates are lines
+                cmd = if rel { b'l' } else { b'L' };
+            }
+            b'L' => {
+                cur = origin + vec2(num()?, num()?);
+                points.push(cur);
+            }
+            b'H' => {
+                cur.x = origin.x + num()?;
+                points.push(cur);
+            }
+            b'V' => {
+                cur.y = origin.y + num()?;
+                points.push(cur);
+            }
+            b'C' | b'S' => {
+                let c1 = if cmd.eq_ignore_ascii_case(&b'C') {
+                    origin + vec2(num()?, num()?)
+                } else {
+                    last_cubic.map_or(cur, |c: Vec2| 2.0 * cur - c)
+                };
+                let c2 = origin + vec2(num()?, num()?);
+                let to = origin + vec2(num()?, num()?);
+                flatten_cubic(&mut points, [cur, c1, c2, to], 0);
+                cubic = Some(c2);
+                cur = to;
+            }
+            b'Q' | b'T' => {
+                let q = if cmd.eq_ignore_ascii_case(&b'Q') {
+                    origin + vec2(num()?, num()?)
+                } else {
+                    last_quad.map_or(cur, |c: Vec2| 2.0 * cur - c)
+                };
+                let to = origin + vec2(num()?, num()?);
+                let c1 = cur + (q - cur) * 2.0 / 3.0;
+                let c2 = to + (q - to) * 2.0 / 3.0;
+                flatten_cubic(&mut points, [cur, c1, c2, to], 0);
+                quad = Some(q);
+                cur = to;
+            }
+            b'A' => {
+                let r = vec2(num()?, num()?);
+                let rot = num()?;
+                let large = n.flag().ok_or_else(invalid)?;
+                let sweep = n.flag().ok_or_else(invalid)?;
+                let to =
+                    origin + vec2(n.next().ok_or_else(invalid)?, n.next().ok_or_else(invalid)?);
+                flatten_arc(&mut points, cur, r, rot, large, sweep, to);
+                cur = to;
+            }
+            b'Z' => {
+                if points.len() > 1 {
+                    subpaths.push(std::mem::take(&mut points));
+                }
+                cur = start;
+                points.push(cur);
+            }
+            _ => return Err(invalid()),
+        }
+        last_cubic = cubic;
+        last_quad = quad;
+    }
+    if points.len() > 1 {
+        subpaths.push(points);
+    }
+    Ok(subpaths)
+}
+
+fn ellipse(c: Vec2, r: Vec2) -> Vec<Vec2> {
+    let mut points = vec![];
+    flatten_ellipse(&mut points, c, r, 0.0, 0.0, std::f32::consts::TAU);
+    points
+}
+
+// the outlines of a shape element in its own coordinates
+fn shape(tag: &Tag) -> Result<Vec<Vec<Vec2>>, std::io::Error> {
+    let (x, y) = (tag.num("x"), tag.num("y"));
+    Ok(match tag.name {
+        "path" => parse_path(tag.attr("d").unwrap_or_default())?,
+        "polygon" | "polyline" => {
+            let n: Vec<f32> = Numbers::new(tag.attr("points").unwrap_or_default()).collect();
+            vec![n.chunks_exact(2).map(|c| vec2(c[0], c[1])).collect()]
+        }
+        "rect" => {
+            let (w, h) = (tag.num("width"), tag.num("height"));
+            vec![vec![
+                vec2(x, y),
+                vec2(x + w, y),
+                vec2(x + w, y + h),
+                vec2(x, y + h),
+            ]]
+        }
+        "circle" => {
+            let r = tag.num("r");
+            vec![ellipse(vec2(tag.num("cx"), tag.num("cy")), vec2(r, r))]
+        }
+        "ellipse" => vec![ellipse(
+            vec2(tag.num("cx"), tag.num("cy")),
+            vec2(tag.num("rx"), tag.num("ry")),
+        )],
+        _ => vec![],
+    })
+}
+
+pub fn parse(text: &str) -> Result<Level, std::io::Error> {
+    let mut level = Level::default();
+    // transform and layer of every open element
+    let mut stack: Vec<(Affine2, Option<PolygonType>)> = vec![(Affine2::IDENTITY, None)];
+    let mut found_svg = false;
+    for tag in tags(text)? {
+        if tag.closing {
+            if stack.len() > 1 {
+                stack.pop();
+            }
+            continue;
+        }
+        found_svg |= tag.name == "svg";
+        let (parent, layer) = *stack.last().unwrap();
+        let transform = parent
+            * tag
+                .attr("transform")
+                .map_or(Affine2::IDENTITY, parse_transform);
+        let label = tag.label();
+        let layer = match label {
+            "walls" => Some(PolygonType::Wall),
+            "lava" => Some(PolygonType::Lava),
+            _ => layer,
+        };
+
+        if tag.name == "circle" && label.starts_with("star") {
+            let pos = transform.transform_point2(vec2(tag.num("cx"), tag.num("cy")));
+            if label.starts_with("start") {
+                level.start = pos;
+            } else {
+                level.stars.push(Star::new(pos));
+            }
+        } else if let Some(tpe) = layer {
+            for mut points in shape(&tag)? {
+                for p in points.iter_mut() {
+                    *p = transform.transform_point2(*p);
+                }
+                points.dedup_by(|a, b| a.distance(*b) < 1e-3);
+                if points.len() > 2 && points[0].distance(points[points.len() - 1]) < 1e-3 {
+                    points.pop();
+                }
+                if points.len() >= 3 {
+                    level.polygons.push(Polygon {
+                        tpe,
+                        points,
+                        curve: None,
+                        properties: Default::default(),
+                    });
+                }
+            }
+        }
+
+        if !tag.empty {
+            stack.push((transform, layer));
+        }
+    }
+    if !found_svg {
+        return Err(invalid_data("not an SVG file"));
+    }
+
+    level.build();
+    Ok(level)
+}
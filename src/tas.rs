@@ -0,0 +1,278 @@
+// Tool-assisted runs: the simulation is paused and advanced frame by frame
+// or tick by tick, with savestates and rewinding. The inputs of the current
+// branch are kept as a replay that can be exported.
+
+use macroquad::prelude::*;
+
+use crate::bike::{Bike, Direction, Input, WheelInput, PHYSICS_DT};
+use crate::level::Level;
+use crate::replay::Replay;
+use crate::ui::{Align, Ui, TOP_LEFT};
+use crate::LevelTime;
+
+// physics ticks in a frame at 60 fps
+pub const FRAME_TICKS: u32 = 83;
+const SLOTS: usize = 4;
+const MESSAGE_DURATION: f64 = 2.0;
+
+#[derive(Clone)]
+struct State {
+    bike: Bike,
+    stars: Vec<bool>,
+    ticks: u64,
+}
+
+struct Savestate {
+    state: State,
+    replay: Replay,
+    history: Vec<State>,
+}
+
+// inputs that stay on without holding a key
+#[derive(Default)]
+struct Sticky {
+    accelerate: bool,
+    brake: bool,
+    left: bool,
+    right: bool,
+    turn: bool,
+}
+
+pub struct Tas {
+    pub paused: bool,
+    pub replay: Replay,
+    sticky: Sticky,
+    // state at the start of every advance, for rewinding
+    history: Vec<State>,
+    slots: [Option<Savestate>; SLOTS],
+    slot: usize,
+    // real time not simulated yet while running
+    pending: f32,
+    message: Option<(String, f64)>,
+}
+
+impl Tas {
+    pub fn new(level_file: &str, profile: u32) -> Self {
+        Tas {
+            paused: true,
+            replay: Replay::new(level_file, profile),
+            sticky: Default::default(),
+            history: vec![],
+            slots: Default::default(),
+            slot: 0,
+            pending: 0.0,
+            message: None,
+        }
+    }
+
+    pub fn time(&self) -> f32 {
+        self.replay.ticks() as f32 * PHYSICS_DT
+    }
+
+    fn say(&mut self, msg: String) {
+        self.message = Some((msg, get_time()));
+    }
+
+    // held keys combined with sticky inputs
+    fn input(&self, held: &Input) -> Input {
+        let s = &self.sticky;
+        let accelerate = s.accelerate || held.wheel == WheelInput::Accelerate;
+        let brake = s.brake || held.wheel == WheelInput::Break;
+        let left = s.left || held.jump == Some(Direction::Left);
+        let right = s.right || held.jump == Some(Direction::Right);
+        Input {
+            toggle_dir: s.turn || held.toggle_dir,
+            wheel: match (brake, accelerate) {
+                (true, false) => WheelInput::Break,
+                (false, true) => WheelInput::Accelerate,
+                _ => WheelInput::None,
+            },
+            jump: match (left, right) {
+                (true, false) => Some(Direction::Left),
+                (false, true) => Some(Direction::Right),
+                _ => None,
+            },
+        }
+    }
+
+    fn snapshot(&self, bike: &Bike, level: &Level) -> State {
+        State {
+            bike: bike.clone(),
+            stars: level.star_state(),
+            ticks: self.replay.ticks(),
+        }
+    }
+
+    fn restore(&mut self, state: &State, bike: &mut Bike, level: &mut Level) {
+        *bike = state.bike.clone();
+        level.set_star_state(&state.stars);
+        self.replay.truncate(state.ticks);
+    }
+
+    // the run is over once the bike is dead or all stars are collected
+    pub fn over(bike: &Bike, level: &Level) -> bool {
+        !bike.alive || level.stars_left == 0
+    }
+
+    fn advance(&mut self, ticks: u32, input: &Input, bike: &mut Bike, level: &mut Level) {
+        if Self::over(bike, level) {
+            self.paused = true;
+            return;
+        }
+        self.history.push(self.snapshot(bike, level));
+        for _ in 0..ticks {
+            bike.update(PHYSICS_DT, level, input);
+            self.replay.push(input, 1);
+            if Self::over(bike, level) {
+                self.paused = true;
+                break;
+            }
+        }
+    }
+
+    fn rewind(&mut self, bike: &mut Bike, level: &mut Level) {
+        if let Some(state) = self.history.pop() {
+            self.restore(&state, bike, level);
+        }
+    }
+
+    pub fn update(&mut self, dt: f32, held: &Input, bike: &mut Bike, level: &mut Level) {
+        // sticky inputs
+        let s = &mut self.sticky;
+        for (key, flag) in [
+            (KeyCode::Key1, &mut s.accelerate),
+            (KeyCode::Key2, &mut s.brake),
+            (KeyCode::Key3, &mut s.left),
+            (KeyCode::Key4, &mut s.right),
+            (KeyCode::Key5, &mut s.turn),
+        ] {
+            if is_key_pressed(key) {
+                *flag = !*flag;
+            }
+        }
+
+        // savestates
+        if is_key_pressed(KeyCode::Tab) {
+            self.slot = (self.slot + 1) % SLOTS;
+        }
+        if is_key_pressed(KeyCode::K) {
+            self.slots[self.slot] = Some(Savestate {
+                state: self.snapshot(bike, level),
+                replay: self.replay.clone(),
+                history: self.history.clone(),
+            });
+            self.say(format!("SAVED SLOT {}", self.slot + 1));
+        }
+        if is_key_pressed(KeyCode::L) {
+            if let Some(slot) = &self.slots[self.slot] {
+                *bike = slot.state.bike.clone();
+                level.set_star_state(&slot.state.stars);
+                self.replay = slot.replay.clone();
+                self.history = slot.history.clone();
+                self.paused = true;
+                self.say(format!("LOADED SLOT {}", self.slot + 1));
+            }
+        }
+
+        if is_key_pressed(KeyCode::X) {
+            self.export();
+        }
+
+        // hold to keep rewinding
+        if is_key_down(KeyCode::Backspace) {
+            self.paused = true;
+            self.rewind(bike, level);
+            return;
+        }
+
+        if is_key_pressed(KeyCode::P) {
+            self.paused = !self.paused;
+            self.pending = 0.0;
+        }
+        let input = self.input(held);
+        if self.paused {
+            if is_key_pressed(KeyCode::Period) {
+                self.advance(FRAME_TICKS, &input, bike, level);
+            }
+            if is_key_pressed(KeyCode::Comma) {
+                self.advance(1, &input, bike, level);
+            }
+        } else {
+            self.pending += dt;
+            let ticks = (self.pending / PHYSICS_DT) as u32;
+            self.pending -= ticks as f32 * PHYSICS_DT;
+            if ticks > 0 {
+                self.advance(ticks, &input, bike, level);
+            }
+        }
+    }
+
+    fn export(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let level = &self.replay.level;
+            let stem = level
+                .rsplit_once('.')
+                .map_or(level.as_str(), |(stem, _)| stem);
+            let path = format!("{}_tas.replay", stem);
+            let msg = match self.replay.save(&path) {
+                Ok(()) => format!("EXPORTED {}", path),
+                Err(e) => format!("ERROR: {}", e),
+            };
+            self.say(msg);
+        }
+    }
+
+    pub fn draw_hud(&self, bike: &Bike, level: &Level, ui: &Ui) {
+        let ticks = self.replay.ticks();
+        let status = if level.stars_left == 0 {
+            format!("FINISHED {}", LevelTime::new(self.time()))
+        } else if !bike.alive {
+            "CRASHED".to_string()
+        } else if self.paused {
+            "PAUSED".to_string()
+        } else {
+            "RUNNING".to_string()
+        };
+        let s = &self.sticky;
+        let sticky: String = [
+            (s.accelerate, "GAS "),
+            (s.brake, "BRAKE "),
+            (s.left, "LEFT "),
+            (s.right, "RIGHT "),
+            (s.turn, "TURN "),
+        ]
+        .iter()
+        .filter(|(on, _)| *on)
+        .map(|(_, name)| *name)
+        .collect();
+        let slots: String = (0..SLOTS)
+            .map(|i| {
+                let c = if self.slots[i].is_some() { '*' } else { '-' };
+                if i == self.slot {
+                    format!("[{}]", c)
+                } else {
+                    format!(" {} ", c)
+                }
+            })
+            .collect();
+
+        let mut lines = vec![
+            format!("TAS {}", status),
+            format!("FRAME {}  TICK {}", ticks / FRAME_TICKS as u64, ticks),
+            format!("STICKY {}", sticky),
+            format!("SLOTS {}", slots),
+            "P RUN  . FRAME  , TICK  1-5 STICKY".to_string(),
+            "K SAVE  L LOAD  TAB SLOT  BACKSPACE REWIND  X EXPORT".to_string(),
+        ];
+        if let Some((msg, t)) = &self.message {
+            if get_time() - t < MESSAGE_DURATION {
+                lines.push(msg.clone());
+            }
+        }
+        for (i, line) in lines.iter().enumerate() {
+            let at = ui.at(TOP_LEFT, vec2(5.0, 28.0 + i as f32 * 12.0));
+            ui.text(line, at, Align::Left, &ui.small);
+        }
+    }
+}
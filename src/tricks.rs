@@ -0,0 +1,197 @@
+use std::f32::consts::PI;
+
+use crate::bike::{Bike, Direction};
+
+// time a wheel may lose contact before a wheelie ends
+const CONTACT_GRACE: f32 = 0.05;
+// shorter flights are just bumps
+const MIN_AIR_TIME: f32 = 0.2;
+const MIN_CLEAN_LANDING_AIR_TIME: f32 = 0.5;
+const CLEAN_LANDING_WINDOW: f32 = 0.15;
+const MIN_WHEELIE_TIME: f32 = 0.5;
+// rotation that may be missing from a full turn for it to count as a flip
+const FLIP_TOLERANCE: f32 = 0.25 * PI;
+
+const FLIP_POINTS: u32 = 500;
+const WHEELIE_POINTS: f32 = 100.0;
+const CLEAN_LANDING_POINTS: u32 = 100;
+
+pub const POPUP_DURATION: f32 = 1.5;
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum TrickKind {
+    Backflip(u32),
+    Frontflip(u32),
+    Wheelie,
+    Stoppie,
+    CleanLanding,
+}
+
+#[derive(Clone, Copy)]
+pub struct Trick {
+    pub kind: TrickKind,
+    pub points: u32,
+    pub time: f32,
+}
+
+impl std::fmt::Display for TrickKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let flip = |f: &mut std::fmt::Formatter<'_>, n: u32, name: &str| match n {
+            1 => write!(f, "{}", name),
+            2 => write!(f, "DOUBLE {}", name),
+            3 => write!(f, "TRIPLE {}", name),
+            _ => write!(f, "{}X {}", n, name),
+        };
+        match *self {
+            TrickKind::Backflip(n) => flip(f, n, "BACKFLIP"),
+            TrickKind::Frontflip(n) => flip(f, n, "FRONTFLIP"),
+            TrickKind::Wheelie => write!(f, "WHEELIE"),
+            TrickKind::Stoppie => write!(f, "STOPPIE"),
+            TrickKind::CleanLanding => write!(f, "CLEAN LANDING"),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Landing {
+    wheel: usize,
+    time: f32,
+}
+
+#[derive(Default, Clone)]
+pub struct TrickDetector {
+    pub points: u32,
+    pub tricks: Vec<Trick>,
+    time: f32,
+    prev_ang: Option<f32>,
+    air_time: f32,
+    rotation: f32,
+    since_contact: [f32; 2],
+    one_wheel: Option<(usize, f32)>,
+    landing: Option<Landing>,
+}
+
+fn wrap_angle(a: f32) -> f32 {
+    (a + PI).rem_euclid(2.0 * PI) - PI
+}
+
+impl TrickDetector {
+    fn add(&mut self, kind: TrickKind, points: u32) {
+        self.points += points;
+        self.tricks.push(Trick {
+            kind,
+            points,
+            time: self.time,
+        });
+    }
+
+    fn land(&mut self, bike: &Bike) {
+        let turns = ((self.rotation.abs() + FLIP_TOLERANCE) / (2.0 * PI)) as u32;
+        if turns > 0 {
+            // positive rotation is clockwise, which tips the nose down when facing right
+            let forward = match bike.dir {
+                Direction::Right => self.rotation > 0.0,
+                Direction::Left => self.rotation < 0.0,
+            };
+            let kind = if forward {
+                TrickKind::Frontflip(turns)
+            } else {
+                TrickKind::Backflip(turns)
+            };
+            self.add(kind, FLIP_POINTS * turns * (turns + 1) / 2);
+        }
+
+        self.landing = None;
+        let contact = bike.wheel_contact();
+        if self.air_time >= MIN_CLEAN_LANDING_AIR_TIME {
+            if contact[0] && contact[1] {
+                self.add(TrickKind::CleanLanding, CLEAN_LANDING_POINTS);
+            } else {
+                self.landing = Some(Landing {
+                    wheel: if contact[0] { 0 } else { 1 },
+                    time: 0.0,
+                });
+            }
+        }
+    }
+
+    fn finish_one_wheel(&mut self, bike: &Bike) {
+        if let Some((wheel, time)) = self.one_wheel.take() {
+            if time < MIN_WHEELIE_TIME {
+                return;
+            }
+            let rear = match bike.dir {
+                Direction::Right => 0,
+                Direction::Left => 1,
+            };
+            let kind = if wheel == rear {
+                TrickKind::Wheelie
+            } else {
+                TrickKind::Stoppie
+            };
+            self.add(kind, (time * WHEELIE_POINTS) as u32);
+        }
+    }
+
+    pub fn update(&mut self, dt: f32, bike: &Bike) {
+        self.time += dt;
+
+        let d = wrap_angle(bike.frame.ang - self.prev_ang.unwrap_or(bike.frame.ang));
+        self.prev_ang = Some(bike.frame.ang);
+
+        // flips
+        let contact = bike.wheel_contact();
+        if !contact[0] && !contact[1] {
+            self.air_time += dt;
+            self.rotation += d;
+        } else {
+            if self.air_time > MIN_AIR_TIME {
+                self.land(bike);
+            }
+            self.air_time = 0.0;
+            self.rotation = 0.0;
+        }
+
+        // clean landing
+        if let Some(landing) = &mut self.landing {
+            landing.time += dt;
+            if contact[1 - landing.wheel] {
+                self.landing = None;
+                self.add(TrickKind::CleanLanding, CLEAN_LANDING_POINTS);
+            } else if landing.time > CLEAN_LANDING_WINDOW {
+                self.landing = None;
+            }
+        }
+
+        // wheelies and stoppies
+        for (t, c) in self.since_contact.iter_mut().zip(contact) {
+            *t = if c { 0.0 } else { *t + dt };
+        }
+        let on = self.since_contact.map(|t| t < CONTACT_GRACE);
+        let wheel = match on {
+            [true, false] => Some(0),
+            [false, true] => Some(1),
+            _ => None,
+        };
+        match (&mut self.one_wheel, wheel) {
+            (Some((w, time)), Some(wheel)) if *w == wheel => *time += dt,
+            _ => {
+                self.finish_one_wheel(bike);
+                self.one_wheel = wheel.map(|w| (w, 0.0));
+            }
+        }
+    }
+
+    // award tricks still in progress when the run ends
+    pub fn finish(&mut self, bike: &Bike) {
+        self.finish_one_wheel(bike);
+    }
+
+    pub fn popups(&self) -> impl Iterator<Item = (&Trick, f32)> {
+        self.tricks
+            .iter()
+            .rev()
+            .map(|t| (t, self.time - t.time))
+            .take_while(|(_, age)| *age < POPUP_DURATION)
+    }
+}
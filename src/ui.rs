@@ -0,0 +1,192 @@
+// Layout of text and lists on the canvas, the area the game draws its HUD
+// and menus into.
+//
+// The canvas is at least 480x270 units and grows with the aspect ratio of the
+// window, so things are placed at an anchor, a point given as a fraction of
+// the canvas like the top right corner (1, 0), plus an offset in units. Text
+// is measured with the game font to be left aligned, centered or right
+// aligned there, and can be shrunk to fit a width. Fonts are rasterized for
+// the pixels the canvas covers, display scaling included, so text stays sharp
+// at any window size.
+
+use macroquad::prelude::*;
+use std::ops::Range;
+
+pub const TOP_LEFT: Vec2 = Vec2::new(0.0, 0.0);
+pub const TOP: Vec2 = Vec2::new(0.5, 0.0);
+pub const TOP_RIGHT: Vec2 = Vec2::new(1.0, 0.0);
+pub const CENTER: Vec2 = Vec2::new(0.5, 0.5);
+pub const BOTTOM_LEFT: Vec2 = Vec2::new(0.0, 1.0);
+pub const BOTTOM: Vec2 = Vec2::new(0.5, 1.0);
+pub const BOTTOM_RIGHT: Vec2 = Vec2::new(1.0, 1.0);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+impl Align {
+    // share of the width left of the point
+    fn factor(self) -> f32 {
+        match self {
+            Align::Left => 0.0,
+            Align::Center => 0.5,
+            Align::Right => 1.0,
+        }
+    }
+}
+
+pub struct Ui<'a> {
+    pub size: Vec2,
+    // HUD, menu and title text
+    pub small: TextParams<'a>,
+    pub menu: TextParams<'a>,
+    pub big: TextParams<'a>,
+}
+
+impl<'a> Ui<'a> {
+    fn with_fonts(font: &'a Font, size: Vec2, raster: impl Fn(f32) -> (u16, f32)) -> Ui<'a> {
+        let params = |units: f32| {
+            let (font_size, font_scale) = raster(units);
+            TextParams {
+                font: Some(font),
+                font_size,
+                font_scale,
+                ..Default::default()
+            }
+        };
+        Ui {
+            size,
+            small: params(11.0),
+            menu: params(20.0),
+            big: params(40.0),
+        }
+    }
+
+    // for the camera in use, which has to show the canvas from (0, 0) to size
+    pub fn new(font: &'a Font, size: Vec2) -> Ui<'a> {
+        Ui::with_fonts(font, size, |units| {
+            let (font_size, font_scale, _) = camera_font_scale(units);
+            (font_size, font_scale)
+        })
+    }
+
+    // for drawing into a texture with the given pixels per unit
+    pub fn with_scale(font: &'a Font, size: Vec2, scale: f32) -> Ui<'a> {
+        Ui::with_fonts(font, size, |units| {
+            let font_size = (units * scale).round().max(1.0) as u16;
+            (font_size, units / font_size as f32)
+        })
+    }
+
+    pub fn at(&self, anchor: Vec2, offset: Vec2) -> Vec2 {
+        anchor * self.size + offset
+    }
+
+    pub fn width(&self, text: &str, style: &TextParams) -> f32 {
+        measure_text(text, style.font, style.font_size, style.font_scale).width
+    }
+
+    // text with its baseline at pos
+    pub fn text(&self, text: &str, pos: Vec2, align: Align, style: &TextParams) {
+        let x = pos.x - align.factor() * self.width(text, style);
+        draw_text_ex(text, x, pos.y, style.clone());
+    }
+
+    // like text, but shrunk to be at most max_width wide
+    pub fn text_fit(
+        &self,
+        text: &str,
+        pos: Vec2,
+        align: Align,
+        max_width: f32,
+        style: &TextParams,
+    ) {
+        let width = self.width(text, style);
+        if width <= max_width {
+            self.text(text, pos, align, style);
+        } else {
+            let style = TextParams {
+                font_scale: style.font_scale * max_width / width,
+                ..style.clone()
+            };
+            self.text(text, pos, align, &style);
+        }
+    }
+
+    // digits at fixed widths, so that running times don't jitter
+    pub fn mono(&self, text: &str, pos: Vec2, align: Align, style: &TextParams) {
+        let scale = (style.font_size as f32) * style.font_scale * 0.1;
+        let advance = |c: char| match c {
+            ':' => 3.0 * scale,
+            _ => 5.0 * scale,
+        };
+        let width: f32 = text.chars().map(advance).sum();
+        let mut x = pos.x - align.factor() * width;
+        for c in text.chars() {
+            let o = match c {
+                ':' => 1.0 * scale,
+                '1' => 1.5 * scale,
+                _ => 0.0,
+            };
+            draw_text_ex(&c.to_string(), x + o, pos.y, style.clone());
+            x += advance(c);
+        }
+    }
+}
+
+// Rows of a list that shows as many as fit and scrolls to keep the selected
+// one in the middle, where it can.
+pub struct List {
+    pub rows: Range<usize>,
+    len: usize,
+    // baseline of the first visible row
+    top: f32,
+    row_height: f32,
+}
+
+impl List {
+    // rows that have their baseline between top and bottom
+    pub fn new(len: usize, selected: usize, top: f32, bottom: f32, row_height: f32) -> List {
+        let fit = ((bottom - top) / row_height).floor() as usize + 1;
+        let n = fit.min(len);
+        let first = selected.saturating_sub(n / 2).min(len - n);
+        List {
+            rows: first..first + n,
+            len,
+            top,
+            row_height,
+        }
+    }
+
+    // baseline of a visible row
+    pub fn y(&self, row: usize) -> f32 {
+        self.top + (row - self.rows.start) as f32 * self.row_height
+    }
+
+    // arrows beside the first and last visible rows, if there are more rows
+    // that way
+    pub fn draw_arrows(&self, x: f32, color: Color) {
+        let s = self.row_height * 0.2;
+        if self.rows.start > 0 {
+            let y = self.y(self.rows.start) - self.row_height * 0.3;
+            draw_triangle(
+                vec2(x, y - s),
+                vec2(x + s, y + s),
+                vec2(x - s, y + s),
+                color,
+            );
+        }
+        if self.rows.end < self.len {
+            let y = self.y(self.rows.end - 1) - self.row_height * 0.3;
+            draw_triangle(
+                vec2(x, y + s),
+                vec2(x - s, y - s),
+                vec2(x + s, y - s),
+                color,
+            );
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use crate::bike::{Bike, BikeParams, Input};
+use crate::level::Level;
+use crate::LevelTime;
+
+#[derive(Default, Clone, Copy)]
+pub struct Racer {
+    pub stars: usize,
+    pub finish: Option<LevelTime>,
+}
+
+pub struct Versus {
+    pub shared_stars: bool,
+    // the second player; the first one is the regular game bike
+    pub bike: Bike,
+    // the second player's copy of the level, unless stars are shared
+    pub level: Option<Level>,
+    pub racers: [Racer; 2],
+}
+
+impl Versus {
+    pub fn new(level: Option<Level>, start: &Level, params: BikeParams) -> Self {
+        Versus {
+            shared_stars: level.is_none(),
+            bike: Bike::new(start.start, params),
+            level,
+            racers: Default::default(),
+        }
+    }
+
+    pub fn level<'a>(&'a self, shared: &'a Level) -> &'a Level {
+        self.level.as_ref().unwrap_or(shared)
+    }
+
+    // advance both players, returns true once the race is over
+    pub fn update(
+        &mut self,
+        dt: f32,
+        time: f32,
+        bike: &mut Bike,
+        level: &mut Level,
+        inputs: [&Input; 2],
+    ) -> bool {
+        for (i, input) in inputs.into_iter().enumerate() {
+            let (b, l) = match i {
+                0 => (&mut *bike, &mut *level),
+                _ => (&mut self.bike, self.level.as_mut().unwrap_or(&mut *level)),
+            };
+            if !b.alive {
+                continue;
+            }
+            let stars_left = l.stars_left;
+            b.update(dt, l, input);
+            self.racers[i].stars += stars_left - l.stars_left;
+            if l.stars_left == 0 && !self.shared_stars {
+                self.racers[i].finish = Some(LevelTime::new(time));
+            }
+        }
+
+        if self.shared_stars && level.stars_left == 0 {
+            for (racer, b) in self.racers.iter_mut().zip([&*bike, &self.bike]) {
+                if b.alive {
+                    racer.finish = Some(LevelTime::new(time));
+                }
+            }
+            return true;
+        }
+        // the first one to collect all stars wins
+        self.racers.iter().any(|r| r.finish.is_some()) || (!bike.alive && !self.bike.alive)
+    }
+
+    // the winning player, if there is one
+    pub fn winner(&self) -> Option<usize> {
+        let [a, b] = self.racers;
+        if self.shared_stars {
+            return match a.stars.cmp(&b.stars) {
+                std::cmp::Ordering::Greater => Some(0),
+                std::cmp::Ordering::Less => Some(1),
+                std::cmp::Ordering::Equal => None,
+            };
+        }
+        match (a.finish, b.finish) {
+            (Some(_), None) => Some(0),
+            (None, Some(_)) => Some(1),
+            (Some(x), Some(y)) if x < y => Some(0),
+            (Some(x), Some(y)) if y < x => Some(1),
+            _ => None,
+        }
+    }
+}